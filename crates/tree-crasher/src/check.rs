@@ -0,0 +1,1172 @@
+//! Running the target and deciding whether its behavior is interesting.
+//!
+//! This is a fork of `treereduce::CmdCheck` that allows more control over how
+//! the target process is spawned. It still implements [`treereduce::Check`] so
+//! that it can be used during reduction.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::OwnedFd;
+use std::os::unix::io::AsRawFd;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use tempfile::{NamedTempFile, TempDir};
+use wait_timeout::ChildExt;
+
+#[derive(Clone, Debug)]
+pub(crate) struct CmdCheck {
+    cmd: String,
+    args: Vec<String>,
+    exit_codes: Vec<i32>,
+    interesting_stderr: Option<Regex>,
+    interesting_stdout: Option<Regex>,
+    uninteresting_stderr: Option<Regex>,
+    uninteresting_stdout: Option<Regex>,
+    /// Matched against stdout followed by stderr
+    interesting_output: Option<Regex>,
+    uninteresting_output: Option<Regex>,
+    temp_dir: PathBuf,
+    needs_file: bool,
+    inherit_stdout: bool,
+    inherit_stderr: bool,
+    timeout: Option<Duration>,
+    cwd: Option<PathBuf>,
+    env: Vec<(String, String)>,
+    interesting_output_bytes: Option<usize>,
+    ignored_signals: Vec<i32>,
+    /// In megabytes
+    mem_limit: Option<u64>,
+    rlimit_nofile: Option<u64>,
+    nice: Option<i32>,
+    /// Speak the protocol in [`crate::persistent`], for a single input
+    persistent: bool,
+    /// Of each of stdout and stderr
+    max_output_bytes: usize,
+}
+
+/// Appended to output that was cut off at `max_output_bytes`
+const TRUNCATED: &[u8] = b"\n[truncated by tree-crasher]\n";
+
+/// Output read from one of the target's pipes, up to a limit
+#[derive(Debug)]
+struct Capture {
+    bytes: Vec<u8>,
+    max: usize,
+    /// Bytes read, including those that weren't kept
+    total: usize,
+}
+
+impl Capture {
+    fn new(max: usize) -> Self {
+        Capture {
+            bytes: Vec::new(),
+            max,
+            total: 0,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) {
+        let room = self.max.saturating_sub(self.bytes.len());
+        self.bytes
+            .extend_from_slice(&chunk[..chunk.len().min(room)]);
+        self.total = self.total.saturating_add(chunk.len());
+    }
+
+    /// The bytes that were kept, and how many were read in all
+    fn finish(&mut self) -> (Vec<u8>, usize) {
+        let mut bytes = std::mem::take(&mut self.bytes);
+        if self.total > bytes.len() {
+            bytes.extend_from_slice(TRUNCATED);
+        }
+        (bytes, self.total)
+    }
+}
+
+/// The target's stdout and stderr, until they're closed, and what's been read
+/// from each
+type Pipes = [(Option<File>, Capture); 2];
+
+/// What the target printed
+#[derive(Debug, Default)]
+pub(crate) struct Output {
+    /// At most `max_output_bytes`, followed by a note if there was more
+    pub(crate) stdout: Vec<u8>,
+    /// As for `stdout`
+    pub(crate) stderr: Vec<u8>,
+    /// Bytes printed to stdout and stderr, including those that weren't kept
+    pub(crate) printed: usize,
+}
+
+/// What became of a single run of the target
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub(crate) enum Outcome {
+    /// Nothing to see here
+    Boring,
+    /// Exceeded the timeout
+    Timeout,
+    /// Matched the interestingness check
+    Interesting,
+    /// Killed by SIGKILL, most likely by the OOM killer
+    Oom,
+    /// Killed by this signal
+    Signal(i32),
+}
+
+/// A regex that matched some output of the target
+#[derive(Debug)]
+pub(crate) struct Match {
+    pub(crate) pattern: String,
+    pub(crate) text: String,
+}
+
+/// Which parts of the interestingness check fired
+#[derive(Debug)]
+pub(crate) struct Matches {
+    pub(crate) exit_code: bool,
+    /// The target said so, with --persistent
+    pub(crate) reply: bool,
+    pub(crate) output_bytes: bool,
+    pub(crate) interesting_stdout: Option<Match>,
+    pub(crate) interesting_stderr: Option<Match>,
+    pub(crate) uninteresting_stdout: Option<Match>,
+    pub(crate) uninteresting_stderr: Option<Match>,
+    pub(crate) interesting_output: Option<Match>,
+    pub(crate) uninteresting_output: Option<Match>,
+}
+
+impl Matches {
+    pub(crate) fn is_interesting(&self) -> bool {
+        (self.exit_code
+            || self.reply
+            || self.output_bytes
+            || self.interesting_stdout.is_some()
+            || self.interesting_stderr.is_some()
+            || self.interesting_output.is_some())
+            && self.uninteresting_stdout.is_none()
+            && self.uninteresting_stderr.is_none()
+            && self.uninteresting_output.is_none()
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct CmdCheckState<'a> {
+    child: Child,
+    /// Input that the target hasn't read yet
+    stdin: Option<Stdin<'a>>,
+    pipes: Pipes,
+    /// Removed on drop, after the target
+    temp_file: Option<InputFile>,
+}
+
+impl Drop for CmdCheckState<'_> {
+    fn drop(&mut self) {
+        // A target that we stopped waiting for, e.g., because reading its
+        // output failed, would otherwise keep running and hold on to its files
+        if let Ok(None) = self.child.try_wait() {
+            self.child.kill().ok();
+            self.child.wait().ok();
+        }
+    }
+}
+
+/// Replaced by the path of the input file
+const FILE_MARKER: &str = "@@";
+/// Replaced by the path of a directory that holds only the input file
+const DIR_MARKER: &str = "@@dir";
+
+/// The file that a target reads its input from, named in its arguments
+#[derive(Debug)]
+pub(crate) struct InputFile {
+    pub(crate) file: NamedTempFile,
+    /// For `@@dir`, removed after `file`
+    dir: Option<TempDir>,
+}
+
+impl InputFile {
+    fn close(self) -> io::Result<()> {
+        self.file.close()?;
+        self.dir.map_or(Ok(()), TempDir::close)
+    }
+}
+
+/// The text after the first `@@` in the arguments (but not `@@dir`), up to the
+/// next marker, e.g., `.c` in `--file=@@.c`
+///
+/// That becomes the end of the file name, so that targets which care about
+/// file extensions see the one they expect. Every `@@` is replaced by the path
+/// without it, so `a.@@.o b.@@.c` names the same file twice.
+fn file_suffix(args: &[String]) -> &str {
+    for arg in args {
+        for (i, _) in arg.match_indices(FILE_MARKER) {
+            if !arg[i..].starts_with(DIR_MARKER) {
+                let rest = &arg[i + FILE_MARKER.len()..];
+                return &rest[..rest.find(FILE_MARKER).unwrap_or(rest.len())];
+            }
+        }
+    }
+    ""
+}
+
+/// Replace every `@@dir` in `arg` by `dir` and every other `@@` by `base`,
+/// passing the text around them through `literal`
+fn expand(arg: &str, dir: &str, base: &str, literal: impl Fn(&str) -> String) -> String {
+    arg.split(DIR_MARKER)
+        .map(|part| {
+            part.split(FILE_MARKER)
+                .map(&literal)
+                .collect::<Vec<_>>()
+                .join(base)
+        })
+        .collect::<Vec<_>>()
+        .join(dir)
+}
+
+/// Quote a string for the shell
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// The target's stdin, written a bit at a time while reading its output so
+/// that neither side blocks on a full pipe
+#[derive(Debug)]
+struct Stdin<'a> {
+    pipe: File,
+    /// Written before `input`, e.g., a frame header
+    header: Vec<u8>,
+    /// Borrowed where possible, since mutants can be large
+    input: Cow<'a, [u8]>,
+    written: usize,
+}
+
+impl<'a> Stdin<'a> {
+    fn new(pipe: File, header: Vec<u8>, input: Cow<'a, [u8]>) -> io::Result<Self> {
+        let fd = pipe.as_raw_fd();
+        // SAFETY: `fd` is open for the lifetime of `pipe`.
+        let ok = unsafe {
+            let flags = libc::fcntl(fd, libc::F_GETFL);
+            flags >= 0 && libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) >= 0
+        };
+        if !ok {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Stdin {
+            pipe,
+            header,
+            input,
+            written: 0,
+        })
+    }
+
+    /// Write as much as fits in the pipe, returning whether there's nothing
+    /// left to write
+    ///
+    /// Targets may exit (or just stop reading) before consuming all of a large
+    /// input, which is not an error on our part.
+    fn advance(&mut self) -> io::Result<bool> {
+        loop {
+            let rest = match self.written.checked_sub(self.header.len()) {
+                None => &self.header[self.written..],
+                Some(n) => &self.input[n..],
+            };
+            if rest.is_empty() {
+                return Ok(true);
+            }
+            match self.pipe.write(rest) {
+                Ok(n) => self.written += n,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(true),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Advance the target's stdin, closing it once everything has been written
+fn advance(stdin: &mut Option<Stdin<'_>>) -> io::Result<()> {
+    if let Some(sin) = stdin {
+        if sin.advance()? {
+            *stdin = None;
+        }
+    }
+    Ok(())
+}
+
+/// Wait up to `ms` milliseconds (or forever, if negative) until the target's
+/// pipes are ready, then write to its stdin and read its output as they allow
+///
+/// Returns how many pipes were ready, or `None` once all of them are closed.
+fn pump(
+    stdin: &mut Option<Stdin<'_>>,
+    pipes: &mut Pipes,
+    ms: libc::c_int,
+) -> io::Result<Option<usize>> {
+    let mut fds: Vec<_> = pipes
+        .iter()
+        .filter_map(|(pipe, _)| pipe.as_ref())
+        .map(|pipe| libc::pollfd {
+            fd: pipe.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    if let Some(sin) = stdin {
+        fds.push(libc::pollfd {
+            fd: sin.pipe.as_raw_fd(),
+            events: libc::POLLOUT,
+            revents: 0,
+        });
+    }
+    if fds.is_empty() {
+        return Ok(None);
+    }
+    let n = loop {
+        // SAFETY: `fds` is a valid array of pollfds of the given length.
+        let n = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, ms) };
+        if n >= 0 {
+            break n;
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    };
+    let ready = |fd| fds.iter().any(|f| f.fd == fd && f.revents != 0);
+    if stdin
+        .as_ref()
+        .is_some_and(|sin| ready(sin.pipe.as_raw_fd()))
+    {
+        advance(stdin)?;
+    }
+    let mut buf = [0u8; 8192];
+    for (pipe, capture) in pipes {
+        let Some(p) = pipe else {
+            continue;
+        };
+        if !ready(p.as_raw_fd()) {
+            continue;
+        }
+        match p.read(&mut buf) {
+            Ok(0) => *pipe = None,
+            Ok(n) => capture.push(&buf[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(Some(usize::try_from(n).unwrap_or_default()))
+}
+
+impl CmdCheck {
+    #[allow(clippy::too_many_arguments)]
+    pub(crate) fn new(
+        cmd: String,
+        args: Vec<String>,
+        exit_codes: Vec<i32>,
+        temp_dir: Option<PathBuf>,
+        interesting_stdout: Option<Regex>,
+        interesting_stderr: Option<Regex>,
+        uninteresting_stdout: Option<Regex>,
+        uninteresting_stderr: Option<Regex>,
+        inherit_stdout: bool,
+        inherit_stderr: bool,
+        timeout: Option<Duration>,
+    ) -> Self {
+        CmdCheck {
+            needs_file: args.iter().any(|s| s.contains(FILE_MARKER)),
+            temp_dir: temp_dir.unwrap_or_else(std::env::temp_dir),
+            cmd,
+            args,
+            exit_codes,
+            interesting_stdout,
+            interesting_stderr,
+            uninteresting_stdout,
+            uninteresting_stderr,
+            interesting_output: None,
+            uninteresting_output: None,
+            inherit_stdout,
+            inherit_stderr,
+            timeout,
+            cwd: None,
+            env: Vec::new(),
+            interesting_output_bytes: None,
+            ignored_signals: Vec::new(),
+            mem_limit: None,
+            rlimit_nofile: None,
+            nice: None,
+            persistent: false,
+            max_output_bytes: usize::MAX,
+        }
+    }
+
+    /// Run the target in this directory rather than the current one
+    pub(crate) fn with_cwd(mut self, cwd: Option<PathBuf>) -> Self {
+        self.cwd = cwd;
+        self
+    }
+
+    /// Set these environment variables for the target
+    pub(crate) fn with_env(mut self, env: Vec<(String, String)>) -> Self {
+        self.env = env;
+        self
+    }
+
+    /// Match these regexes against stdout and stderr together, for patterns
+    /// that span both
+    pub(crate) fn with_output_regexes(
+        mut self,
+        interesting: Option<Regex>,
+        uninteresting: Option<Regex>,
+    ) -> Self {
+        self.interesting_output = interesting;
+        self.uninteresting_output = uninteresting;
+        self
+    }
+
+    /// Consider runs that print more than this many bytes (stdout and stderr
+    /// combined) interesting
+    pub(crate) fn with_interesting_output_bytes(mut self, bytes: Option<usize>) -> Self {
+        self.interesting_output_bytes = bytes;
+        self
+    }
+
+    /// Treat deaths by these signals as boring
+    pub(crate) fn with_ignored_signals(mut self, signals: Vec<i32>) -> Self {
+        self.ignored_signals = signals;
+        self
+    }
+
+    /// Limit the address space of the target to this many megabytes
+    pub(crate) fn with_mem_limit(mut self, megabytes: Option<u64>) -> Self {
+        self.mem_limit = megabytes;
+        self
+    }
+
+    /// Limit the number of files that the target can have open
+    pub(crate) fn with_rlimit_nofile(mut self, files: Option<u64>) -> Self {
+        self.rlimit_nofile = files;
+        self
+    }
+
+    /// Run the target at this nice level
+    pub(crate) fn with_nice(mut self, nice: Option<i32>) -> Self {
+        self.nice = nice;
+        self
+    }
+
+    /// Send inputs framed as for a persistent target, and read its reply
+    pub(crate) fn with_persistent(mut self, persistent: bool) -> Self {
+        self.persistent = persistent;
+        self
+    }
+
+    /// Keep at most this many bytes of each of stdout and stderr, so that a
+    /// chatty target can't use up all the memory
+    pub(crate) fn with_max_output_bytes(mut self, bytes: usize) -> Self {
+        self.max_output_bytes = bytes;
+        self
+    }
+
+    /// The target and its arguments, with `@@` not yet replaced
+    pub(crate) fn argv(&self) -> Vec<String> {
+        std::iter::once(&self.cmd)
+            .chain(&self.args)
+            .cloned()
+            .collect()
+    }
+
+    pub(crate) fn is_persistent(&self) -> bool {
+        self.persistent
+    }
+
+    /// Use this timeout instead of the one given to [`Self::new`]
+    pub(crate) fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub(crate) fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// The target command with its arguments, working directory, environment,
+    /// and limits, but no stdio set up
+    fn command(&self, args: Vec<String>) -> Command {
+        let mut cmd = Command::new(&self.cmd);
+        cmd.args(args);
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd.envs(self.env.iter().map(|(k, v)| (k, v)));
+        let limits: Vec<_> = [
+            (
+                libc::RLIMIT_AS,
+                self.mem_limit.map(|mb| mb.saturating_mul(1024 * 1024)),
+            ),
+            (libc::RLIMIT_NOFILE, self.rlimit_nofile),
+        ]
+        .into_iter()
+        .filter_map(|(resource, value)| {
+            let value = value?;
+            Some((
+                resource,
+                libc::rlimit {
+                    rlim_cur: value,
+                    rlim_max: value,
+                },
+            ))
+        })
+        .collect();
+        if !limits.is_empty() {
+            // SAFETY: setrlimit is async-signal-safe and the limits are plain
+            // data, allocated before forking.
+            unsafe {
+                cmd.pre_exec(move || {
+                    for (resource, limit) in &limits {
+                        if libc::setrlimit(*resource, limit) != 0 {
+                            return Err(io::Error::last_os_error());
+                        }
+                    }
+                    Ok(())
+                });
+            }
+        }
+        if let Some(nice) = self.nice {
+            // SAFETY: setpriority is a plain system call.
+            unsafe {
+                cmd.pre_exec(move || {
+                    if libc::setpriority(libc::PRIO_PROCESS, 0, nice) == 0 {
+                        Ok(())
+                    } else {
+                        Err(io::Error::last_os_error())
+                    }
+                });
+            }
+        }
+        cmd
+    }
+
+    /// Start a long-running target for [`crate::persistent::Server`]
+    ///
+    /// Its stderr isn't captured, since there's no telling which input it
+    /// belongs to.
+    pub(crate) fn spawn_persistent(&self) -> io::Result<Child> {
+        debug_assert!(self.persistent && !self.needs_file);
+        self.command(self.args.clone())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Self::stdio(self.inherit_stderr, false))
+            .spawn()
+    }
+
+    /// The file that [`crate::forkserver::ForkServer`] writes inputs to, and
+    /// the arguments with `@@` replaced by its path
+    #[cfg(feature = "forkserver")]
+    pub(crate) fn forkserver_input(&self) -> io::Result<(InputFile, Vec<String>)> {
+        if self.needs_file {
+            self.args_with_file()
+        } else {
+            let file = InputFile {
+                file: self.temp_file("")?,
+                dir: None,
+            };
+            Ok((file, self.args.clone()))
+        }
+    }
+
+    /// The command to start an AFL forkserver, which reads `input` on stdin
+    /// unless the arguments refer to it
+    ///
+    /// Its output isn't captured, since it's shared by all of its children.
+    #[cfg(feature = "forkserver")]
+    pub(crate) fn forkserver_command(
+        &self,
+        args: Vec<String>,
+        input: &NamedTempFile,
+    ) -> io::Result<Command> {
+        let mut cmd = self.command(args);
+        cmd.stdin(if self.needs_file {
+            Stdio::null()
+        } else {
+            // The same open file, so that rewinding it rewinds the target's
+            Stdio::from(input.as_file().try_clone()?)
+        })
+        .stdout(Self::stdio(self.inherit_stdout, false))
+        .stderr(Self::stdio(self.inherit_stderr, false));
+        Ok(cmd)
+    }
+
+    fn temp_file(&self, rest: &str) -> io::Result<NamedTempFile> {
+        let mut builder = tempfile::Builder::new();
+        if rest.is_empty() {
+            builder.tempfile_in(&self.temp_dir)
+        } else {
+            builder
+                .prefix("tree-crasher-tmp-")
+                .suffix(rest)
+                .tempfile_in(&self.temp_dir)
+        }
+    }
+
+    /// Replace `@@` with the path to a temporary file, and `@@dir` with a
+    /// temporary directory that it's in
+    fn args_with_file(&self) -> io::Result<(InputFile, Vec<String>)> {
+        debug_assert!(self.needs_file);
+        let suffix = file_suffix(&self.args);
+        let input = if self.args.iter().any(|a| a.contains(DIR_MARKER)) {
+            let dir = tempfile::Builder::new()
+                .prefix("tree-crasher-tmp-")
+                .tempdir_in(&self.temp_dir)?;
+            let file = tempfile::Builder::new()
+                .prefix("input")
+                .suffix(suffix)
+                .rand_bytes(0)
+                .tempfile_in(dir.path())?;
+            InputFile {
+                file,
+                dir: Some(dir),
+            }
+        } else {
+            InputFile {
+                file: self.temp_file(suffix)?,
+                dir: None,
+            }
+        };
+        let path = input.file.path().to_string_lossy();
+        let base = &path[..path.len() - suffix.len()];
+        let dir = input
+            .dir
+            .as_ref()
+            .map(|d| d.path().to_string_lossy())
+            .unwrap_or_default();
+        let args = self
+            .args
+            .iter()
+            .map(|a| expand(a, &dir, base, str::to_string))
+            .collect();
+        Ok((input, args))
+    }
+
+    /// A shell script that runs the target on `input`, a file in the same
+    /// directory as the script, just like the fuzzer would
+    pub(crate) fn script(&self, input: &str) -> String {
+        let mut script = String::from("#!/bin/sh\n");
+        script += "dir=$(cd \"$(dirname \"$0\")\" && pwd)\n";
+        script += &format!("input=\"$dir\"/{}\n", shell_quote(input));
+        // As in `args_with_file`, `$input` followed by the suffix is the file
+        let suffix = file_suffix(&self.args);
+        let quote = |s: &str| {
+            if s.is_empty() {
+                String::new()
+            } else {
+                shell_quote(s)
+            }
+        };
+        if self.args.iter().any(|a| a.contains(DIR_MARKER)) {
+            script += "tmp=$(mktemp -d)\n";
+            script += &format!("cp \"$input\" \"$tmp\"/input{}\n", quote(suffix));
+            script += "input=\"$tmp\"/input\n";
+        } else if self.needs_file && !suffix.is_empty() {
+            script += &format!("cp \"$input\" \"$input\"{}\n", shell_quote(suffix));
+        }
+        let mut argv = vec![shell_quote(&self.cmd)];
+        for arg in &self.args {
+            argv.push(if arg.contains(FILE_MARKER) {
+                expand(arg, "\"$tmp\"", "\"$input\"", quote)
+            } else {
+                shell_quote(arg)
+            });
+        }
+        if let Some(cwd) = &self.cwd {
+            script += &format!("cd {}\n", shell_quote(&cwd.to_string_lossy()));
+        }
+        for (key, value) in &self.env {
+            script += &format!("export {key}={}\n", shell_quote(value));
+        }
+        if let Some(mb) = self.mem_limit {
+            script += &format!("ulimit -v {}\n", mb.saturating_mul(1024));
+        }
+        if let Some(n) = self.rlimit_nofile {
+            script += &format!("ulimit -n {n}\n");
+        }
+        script += &format!("exec {}", argv.join(" "));
+        if !self.needs_file {
+            script += " < \"$input\"";
+        }
+        script.push('\n');
+        script
+    }
+
+    fn stdio(inherit: bool, captured: bool) -> Stdio {
+        if inherit {
+            Stdio::inherit()
+        } else if captured {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        }
+    }
+
+    /// Start the target on an input, which is borrowed for as long as it's
+    /// being written
+    pub(crate) fn exec<'a>(&self, inp: impl Into<Cow<'a, [u8]>>) -> io::Result<CmdCheckState<'a>> {
+        let mut inp = inp.into();
+        let header = if self.persistent {
+            crate::persistent::frame_header(&inp)?.to_vec()
+        } else {
+            Vec::new()
+        };
+        let (mut temp_file, args) = if self.needs_file {
+            let (file, args) = self.args_with_file()?;
+            (Some(file), args)
+        } else {
+            (None, self.args.clone())
+        };
+        if let Some(tf) = &mut temp_file {
+            tf.file.write_all(&inp)?;
+            inp = Cow::Borrowed(&[]);
+        }
+
+        let mut cmd = self.command(args);
+        cmd.stdin(Stdio::piped())
+            .stdout(if self.persistent {
+                Stdio::piped()
+            } else {
+                Self::stdio(
+                    self.inherit_stdout,
+                    self.interesting_stdout.is_some()
+                        || self.interesting_output.is_some()
+                        || self.interesting_output_bytes.is_some(),
+                )
+            })
+            .stderr(Self::stdio(
+                self.inherit_stderr,
+                self.interesting_stderr.is_some()
+                    || self.interesting_output.is_some()
+                    || self.interesting_output_bytes.is_some(),
+            ));
+        let mut child = cmd.spawn()?;
+        let pipe = File::from(OwnedFd::from(child.stdin.take().unwrap()));
+        let mut stdin = Some(Stdin::new(pipe, header, inp)?);
+        advance(&mut stdin)?;
+        let pipes = [
+            (
+                child.stdout.take().map(|p| File::from(OwnedFd::from(p))),
+                Capture::new(self.max_output_bytes),
+            ),
+            (
+                child.stderr.take().map(|p| File::from(OwnedFd::from(p))),
+                Capture::new(self.max_output_bytes),
+            ),
+        ];
+        Ok(CmdCheckState {
+            child,
+            stdin,
+            pipes,
+            temp_file,
+        })
+    }
+
+    /// Evaluate each part of the interestingness check separately
+    pub(crate) fn matches(&self, status: &ExitStatus, output: &Output) -> Matches {
+        let code = status.code().or_else(|| status.signal().map(|c| c + 128));
+        // The reply isn't part of the target's output
+        let (reply, stdout) = match output.stdout.split_first() {
+            Some((reply, rest)) if self.persistent => (*reply != 0, rest),
+            _ => (false, output.stdout.as_slice()),
+        };
+        let printed = output.printed - (output.stdout.len() - stdout.len());
+        let stderr = output.stderr.as_slice();
+        let out_str = String::from_utf8_lossy(stdout);
+        let err_str = String::from_utf8_lossy(stderr);
+        let all_str = if self.interesting_output.is_some() || self.uninteresting_output.is_some() {
+            String::from_utf8_lossy(&[stdout, stderr].concat()).into_owned()
+        } else {
+            String::new()
+        };
+        let find = |rx: &Option<Regex>, s: &str| {
+            let rx = rx.as_ref()?;
+            rx.find(s).map(|m| Match {
+                pattern: rx.as_str().to_string(),
+                text: m.as_str().to_string(),
+            })
+        };
+        Matches {
+            exit_code: self.exit_codes.iter().any(|c| Some(*c) == code),
+            reply,
+            output_bytes: self.interesting_output_bytes.is_some_and(|n| printed > n),
+            interesting_stdout: find(&self.interesting_stdout, &out_str),
+            interesting_stderr: find(&self.interesting_stderr, &err_str),
+            uninteresting_stdout: find(&self.uninteresting_stdout, &out_str),
+            uninteresting_stderr: find(&self.uninteresting_stderr, &err_str),
+            interesting_output: find(&self.interesting_output, &all_str),
+            uninteresting_output: find(&self.uninteresting_output, &all_str),
+        }
+    }
+
+    fn is_interesting(&self, status: &ExitStatus, output: &Output) -> bool {
+        self.matches(status, output).is_interesting()
+    }
+
+    /// Decide what to make of a run of the target
+    ///
+    /// `status` is `None` if the target timed out.
+    pub(crate) fn classify(&self, status: Option<ExitStatus>, output: &Output) -> Outcome {
+        let Some(status) = status else {
+            return Outcome::Timeout;
+        };
+        match status.signal() {
+            Some(s) if self.ignored_signals.contains(&s) => Outcome::Boring,
+            Some(libc::SIGKILL) => Outcome::Oom,
+            Some(s) => Outcome::Signal(s),
+            None if self.is_interesting(&status, output) => Outcome::Interesting,
+            None => Outcome::Boring,
+        }
+    }
+
+    /// Whether a run counts as a crash, which is what reduction preserves
+    pub(crate) fn is_crash(&self, status: Option<ExitStatus>, output: &Output) -> bool {
+        matches!(
+            self.classify(status, output),
+            Outcome::Interesting | Outcome::Signal(_)
+        )
+    }
+
+    /// Write the rest of the target's stdin and read its stdout and stderr
+    /// while it runs, so that it doesn't block on a full pipe, until all are
+    /// closed or `deadline` passes
+    ///
+    /// Output past `max_output_bytes` is read but not kept. Returns whether
+    /// the deadline passed.
+    fn collect(state: &mut CmdCheckState<'_>, deadline: Option<Instant>) -> io::Result<bool> {
+        loop {
+            let ms = match deadline {
+                None => -1,
+                Some(d) => {
+                    let left = d.saturating_duration_since(Instant::now());
+                    libc::c_int::try_from(left.as_millis()).unwrap_or(libc::c_int::MAX)
+                }
+            };
+            match pump(&mut state.stdin, &mut state.pipes, ms)? {
+                None => return Ok(false),
+                Some(0) => return Ok(true),
+                Some(_) => (),
+            }
+        }
+    }
+
+    /// What's been read from the target's stdout and stderr
+    fn output(state: &mut CmdCheckState<'_>) -> Output {
+        let [(_, stdout), (_, stderr)] = &mut state.pipes;
+        let ((stdout, out_len), (stderr, err_len)) = (stdout.finish(), stderr.finish());
+        Output {
+            stdout,
+            stderr,
+            printed: out_len.saturating_add(err_len),
+        }
+    }
+
+    /// Run the target to completion on an input
+    pub(crate) fn run(&self, inp: &[u8]) -> io::Result<(Option<ExitStatus>, Output)> {
+        let state = self.exec(inp)?;
+        self.wait_with_output(state)
+    }
+
+    /// Returns `None` for the exit status if the target timed out, along with
+    /// whatever output it produced until then.
+    pub(crate) fn wait_with_output(
+        &self,
+        mut state: CmdCheckState<'_>,
+    ) -> io::Result<(Option<ExitStatus>, Output)> {
+        let deadline = self.timeout.map(|t| Instant::now() + t);
+        let timed_out = Self::collect(&mut state, deadline)?;
+        // The target may close its stdout and stderr before exiting
+        let status = match deadline {
+            _ if timed_out => None,
+            Some(d) => state
+                .child
+                .wait_timeout(d.saturating_duration_since(Instant::now()))?,
+            None => Some(state.child.wait()?),
+        };
+        if status.is_none() {
+            state.child.kill()?;
+            state.child.wait()?;
+        }
+        Ok((status, Self::output(&mut state)))
+    }
+}
+
+impl treereduce::Check for CmdCheck {
+    type State = CmdCheckState<'static>;
+
+    fn start(&self, stdin: &[u8]) -> io::Result<Self::State> {
+        // The state outlives `stdin`
+        self.exec(stdin.to_vec())
+    }
+
+    fn cancel(&self, mut state: Self::State) -> io::Result<()> {
+        state.child.kill()?;
+        state.child.wait()?;
+        if let Some(tf) = state.temp_file.take() {
+            tf.close()?;
+        }
+        Ok(())
+    }
+
+    fn try_wait(&self, state: &mut Self::State) -> io::Result<Option<bool>> {
+        // Keep the pipes moving, or the target may never exit
+        pump(&mut state.stdin, &mut state.pipes, 0)?;
+        match state.child.try_wait()? {
+            None => Ok(None),
+            Some(status) => {
+                state.stdin = None;
+                Self::collect(state, None)?;
+                let output = Self::output(state);
+                Ok(Some(self.is_crash(Some(status), &output)))
+            }
+        }
+    }
+
+    fn wait(&self, state: Self::State) -> io::Result<bool> {
+        let (status, output) = self.wait_with_output(state)?;
+        Ok(self.is_crash(status, &output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+    use crate::testing::sh;
+    use crate::Args;
+
+    #[test]
+    fn expand_replaces_file_marker() {
+        let lit = |s: &str| s.to_string();
+        assert_eq!(expand("@@", "", "/tmp/x", lit), "/tmp/x");
+        assert_eq!(expand("--file=@@.c", "", "/tmp/x", lit), "--file=/tmp/x.c");
+    }
+
+    #[test]
+    fn expand_markers() {
+        let lit = |s: &str| s.to_string();
+        assert_eq!(expand("@@:@@.o", "", "/tmp/x", lit), "/tmp/x:/tmp/x.o");
+        assert_eq!(expand("-I@@dir", "/tmp/d", "/tmp/d/input", lit), "-I/tmp/d");
+        assert_eq!(
+            expand("@@dir/@@", "/tmp/d", "/tmp/d/input", lit),
+            "/tmp/d//tmp/d/input"
+        );
+        assert_eq!(expand("--verbose", "/tmp/d", "/tmp/x", lit), "--verbose");
+    }
+
+    #[test]
+    fn file_suffixes() {
+        let args = |a: &[&str]| a.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        assert_eq!(file_suffix(&args(&["-c", "@@.c"])), ".c");
+        assert_eq!(file_suffix(&args(&["@@dir", "a.@@.o", "@@.c"])), ".o");
+        assert_eq!(file_suffix(&args(&["@@.c@@.h"])), ".c");
+        assert_eq!(file_suffix(&args(&["-"])), "");
+    }
+
+    #[test]
+    fn input_in_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let chk = CmdCheck {
+            temp_dir: dir.path().to_path_buf(),
+            ..sh(
+                "cmp \"$1\" \"$2\" && cat \"$1\" - && echo && echo \"$1\"",
+                &["@@", "@@"],
+            )
+        };
+        let (status, output) = chk.run(b"int x;").unwrap();
+        assert!(status.is_some_and(|s| s.success()));
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        let (input, path) = stdout.split_once('\n').unwrap();
+        assert_eq!(input, "int x;");
+        assert!(path.starts_with(&*dir.path().to_string_lossy()));
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn input_in_dir() {
+        let chk = sh(
+            "test \"$1\" = \"$(dirname \"$2\")\" && cat \"$2\"",
+            &["@@dir", "@@.c"],
+        );
+        let (status, output) = chk.run(b"int x;").unwrap();
+        assert!(status.is_some_and(|s| s.success()));
+        assert_eq!(output.stdout, b"int x;");
+    }
+
+    #[test]
+    fn capture_truncates() {
+        let mut cap = Capture::new(4);
+        cap.push(b"abc");
+        cap.push(b"defg");
+        let (bytes, total) = cap.finish();
+        assert_eq!(total, 7);
+        assert_eq!(bytes, [&b"abcd"[..], TRUNCATED].concat());
+
+        let mut cap = Capture::new(4);
+        cap.push(b"abcd");
+        assert_eq!(cap.finish(), (b"abcd".to_vec(), 4));
+    }
+
+    #[test]
+    fn classify_exit_code() {
+        let chk = sh("exit \"$1\"", &["1"]);
+        let (status, output) = chk.run(b"").unwrap();
+        assert_eq!(chk.classify(status, &output), Outcome::Interesting);
+        let chk = sh("exit \"$1\"", &["0"]);
+        let (status, output) = chk.run(b"").unwrap();
+        assert_eq!(chk.classify(status, &output), Outcome::Boring);
+    }
+
+    #[test]
+    fn classify_each_outcome() {
+        let chk = sh("", &[]);
+        let exited = |code| Some(ExitStatus::from_raw(code << 8));
+        let killed = |signal| Some(ExitStatus::from_raw(signal));
+        let output = Output::default();
+        assert_eq!(chk.classify(exited(0), &output), Outcome::Boring);
+        assert_eq!(chk.classify(exited(1), &output), Outcome::Interesting);
+        assert_eq!(chk.classify(None, &output), Outcome::Timeout);
+        assert_eq!(chk.classify(killed(libc::SIGKILL), &output), Outcome::Oom);
+        assert_eq!(
+            chk.classify(killed(libc::SIGSEGV), &output),
+            Outcome::Signal(libc::SIGSEGV)
+        );
+        let chk = chk.with_ignored_signals(vec![libc::SIGSEGV]);
+        assert_eq!(
+            chk.classify(killed(libc::SIGSEGV), &output),
+            Outcome::Boring
+        );
+        assert_eq!(
+            chk.classify(killed(libc::SIGABRT), &output),
+            Outcome::Signal(libc::SIGABRT)
+        );
+    }
+
+    #[test]
+    fn classify_by_output() {
+        let chk = sh("", &[]).with_output_regexes(
+            Some(Regex::new("AddressSanitizer").unwrap()),
+            Some(Regex::new("out of memory").unwrap()),
+        );
+        let exited = Some(ExitStatus::from_raw(0));
+        let output = |stderr: &str| Output {
+            stdout: Vec::new(),
+            stderr: stderr.as_bytes().to_vec(),
+            printed: stderr.len(),
+        };
+        let asan = output("==1==ERROR: AddressSanitizer: heap-use-after-free");
+        assert_eq!(chk.classify(exited, &asan), Outcome::Interesting);
+        let oom = output("==1==ERROR: AddressSanitizer: out of memory");
+        assert_eq!(chk.classify(exited, &oom), Outcome::Boring);
+    }
+
+    #[test]
+    fn target_runs_in_cwd() {
+        let dir = crate::testing::corpus(&[("marker", "there")]);
+        let chk = sh("grep -q there marker", &[]).with_cwd(Some(dir.path().to_path_buf()));
+        let (status, _) = chk.run(b"").unwrap();
+        assert!(status.is_some_and(|s| s.success()));
+    }
+
+    #[test]
+    fn output_is_bounded() {
+        let chk = sh("head -c 100000 /dev/zero", &[]).with_max_output_bytes(1000);
+        let (status, output) = chk.run(b"").unwrap();
+        assert!(status.is_some_and(|s| s.success()));
+        assert_eq!(output.stdout.len(), 1000 + TRUNCATED.len());
+        assert!(output.stdout.ends_with(TRUNCATED));
+        assert_eq!(output.printed, 100_000);
+    }
+
+    #[test]
+    fn output_bytes_counts_truncated_output() {
+        let chk = sh("head -c 100000 /dev/zero", &[])
+            .with_max_output_bytes(1000)
+            .with_interesting_output_bytes(Some(50_000));
+        let (status, output) = chk.run(b"").unwrap();
+        assert!(chk.matches(&status.unwrap(), &output).output_bytes);
+        assert_eq!(chk.classify(status, &output), Outcome::Interesting);
+    }
+
+    #[test]
+    fn output_bytes() {
+        let chk = |n| sh("echo hello world", &[]).with_interesting_output_bytes(Some(n));
+        let (status, output) = chk(5).run(b"").unwrap();
+        assert_eq!(chk(5).classify(status, &output), Outcome::Interesting);
+        assert_eq!(chk(100).classify(status, &output), Outcome::Boring);
+    }
+
+    #[test]
+    fn output_bytes_conflicts_with_debug() {
+        let argv = [
+            "tree-crasher",
+            "--debug",
+            "--interesting-output-bytes",
+            "5",
+            "corpus",
+            "true",
+        ];
+        assert!(Args::try_parse_from(argv).is_err());
+        assert!(Args::try_parse_from(argv.iter().filter(|a| **a != "--debug")).is_ok());
+    }
+
+    #[test]
+    fn stdin_is_written_while_reading_output() {
+        // Fills its stdout pipe before reading any of its input
+        let chk = sh("head -c 1000000 /dev/zero; cat", &[]);
+        let inp: Vec<u8> = (0..1_000_000).map(|i| (i % 251) as u8).collect();
+        let (status, output) = chk.run(&inp).unwrap();
+        assert!(status.is_some_and(|s| s.success()));
+        assert_eq!(output.stdout.len(), 2_000_000);
+        assert!(output.stdout[..1_000_000].iter().all(|b| *b == 0));
+        assert_eq!(&output.stdout[1_000_000..], inp.as_slice());
+    }
+
+    #[test]
+    fn timeout_covers_writing_stdin() {
+        let chk = sh("exec sleep 60", &[]).with_timeout(Some(Duration::from_millis(200)));
+        let start = Instant::now();
+        let (status, output) = chk.run(&vec![0; 10_000_000]).unwrap();
+        assert!(start.elapsed() < Duration::from_secs(30));
+        assert_eq!(chk.classify(status, &output), Outcome::Timeout);
+    }
+
+    #[test]
+    fn try_wait_reads_output() {
+        use treereduce::Check;
+        // Blocks on a full stdout pipe unless it's read before the target exits
+        let chk = sh("head -c 1000000 /dev/zero; exit 1", &[]);
+        let mut state = chk.start(b"").unwrap();
+        let start = Instant::now();
+        let crashed = loop {
+            if let Some(crashed) = chk.try_wait(&mut state).unwrap() {
+                break crashed;
+            }
+            assert!(start.elapsed() < Duration::from_secs(30));
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert!(crashed);
+    }
+
+    #[test]
+    fn reduction_agrees_with_fuzzing() {
+        use treereduce::Check;
+        // Not in the interesting exit codes, but a crash nonetheless
+        let chk = sh("kill -SEGV $$", &[]);
+        assert!(chk.wait(chk.start(b"").unwrap()).unwrap());
+        // Exits with 137 like an interesting exit code would, but it's an OOM
+        let mut chk = sh("kill -KILL $$", &[]);
+        chk.exit_codes.push(137);
+        assert!(!chk.wait(chk.start(b"").unwrap()).unwrap());
+    }
+}
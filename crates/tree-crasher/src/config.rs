@@ -0,0 +1,145 @@
+//! Reading default command-line options from a TOML file.
+
+use std::ffi::OsString;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser};
+
+use crate::{read_file, Args};
+
+/// Turn one entry of the config file into command-line flags
+fn flags(key: &str, long: &str, value: &toml::Value) -> Result<Vec<OsString>> {
+    let flag = |v: String| OsString::from(format!("--{long}={v}"));
+    Ok(match value {
+        toml::Value::Boolean(true) => vec![OsString::from(format!("--{long}"))],
+        toml::Value::Boolean(false) => Vec::new(),
+        toml::Value::String(s) => vec![flag(s.clone())],
+        toml::Value::Integer(n) => vec![flag(n.to_string())],
+        toml::Value::Float(x) => vec![flag(x.to_string())],
+        toml::Value::Array(values) => {
+            let mut flags = Vec::new();
+            for v in values {
+                flags.extend(self::flags(key, long, v)?);
+            }
+            flags
+        }
+        toml::Value::Datetime(_) | toml::Value::Table(_) => {
+            anyhow::bail!("Unsupported value for {key}: {value}")
+        }
+    })
+}
+
+/// Flags for the options in the config file that weren't given explicitly
+fn config_flags(path: &PathBuf, matches: &ArgMatches) -> Result<Vec<OsString>> {
+    let bytes = read_file(path)?;
+    let text = String::from_utf8(bytes)
+        .with_context(|| format!("Config file {} is not UTF-8", path.display()))?;
+    let table: toml::Table =
+        toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+    let cmd = Args::command();
+    let options: Vec<_> = cmd
+        .get_arguments()
+        .filter(|a| a.get_long().is_some() && a.get_id() != "config")
+        .collect();
+    let mut flags = Vec::new();
+    for (key, value) in &table {
+        let Some(arg) = options.iter().find(|a| a.get_id() == key.as_str()) else {
+            let mut known: Vec<_> = options.iter().map(|a| a.get_id().as_str()).collect();
+            known.sort_unstable();
+            anyhow::bail!(
+                "Unknown key {key} in config file {}; expected one of: {}",
+                path.display(),
+                known.join(", ")
+            );
+        };
+        if matches.value_source(key) == Some(clap::parser::ValueSource::CommandLine) {
+            continue;
+        }
+        flags.extend(self::flags(key, arg.get_long().unwrap(), value)?);
+    }
+    Ok(flags)
+}
+
+/// Parse the command line, filling in options from `--config` if given
+///
+/// Options given on the command line take precedence over the config file.
+pub(crate) fn parse_args() -> Result<Args> {
+    parse_args_from(std::env::args_os().collect())
+}
+
+/// As for [`parse_args`], but with this command line
+fn parse_args_from(argv: Vec<OsString>) -> Result<Args> {
+    let matches = Args::command().get_matches_from(&argv);
+    let Some(path) = matches.get_one::<PathBuf>("config") else {
+        return Ok(Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit()));
+    };
+    let flags = config_flags(path, &matches)?;
+    Ok(Args::parse_from(
+        argv.iter().take(1).chain(&flags).chain(argv.iter().skip(1)),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn flags_of(value: toml::Value) -> Vec<OsString> {
+        flags("max_size", "max-size", &value).unwrap()
+    }
+
+    #[test]
+    fn keys_to_flags() {
+        assert_eq!(flags_of(toml::Value::Integer(4096)), ["--max-size=4096"]);
+        assert_eq!(flags_of(toml::Value::from("x")), ["--max-size=x"]);
+        assert_eq!(flags_of(toml::Value::Boolean(true)), ["--max-size"]);
+        assert!(flags_of(toml::Value::Boolean(false)).is_empty());
+        assert_eq!(
+            flags_of(toml::Value::from(vec![1, 2])),
+            ["--max-size=1", "--max-size=2"]
+        );
+        assert!(flags(
+            "max_size",
+            "max-size",
+            &toml::Value::Table(toml::Table::new())
+        )
+        .is_err());
+    }
+
+    /// Parse the command line with a config file holding `toml`
+    fn parse(toml: &str, argv: &[&str]) -> Result<Args> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, toml).unwrap();
+        let argv = ["tree-crasher", "--config", path.to_str().unwrap()]
+            .iter()
+            .chain(argv)
+            .chain(&["corpus", "true"])
+            .map(OsString::from)
+            .collect();
+        parse_args_from(argv)
+    }
+
+    #[test]
+    fn config_file_sets_defaults() {
+        let args = parse("timeout = 123\njobs = 3\nfail_on_crash = true\n", &[]).unwrap();
+        assert_eq!(args.timeout, 123);
+        assert_eq!(args.jobs, 3);
+        assert!(args.fail_on_crash);
+    }
+
+    #[test]
+    fn command_line_overrides_config_file() {
+        let args = parse("timeout = 123\njobs = 3\n", &["--timeout", "7"]).unwrap();
+        assert_eq!(args.timeout, 7);
+        assert_eq!(args.jobs, 3);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let e = parse("timout = 123\n", &[]).unwrap_err();
+        assert!(e.to_string().contains("Unknown key timout"), "{e}");
+    }
+}
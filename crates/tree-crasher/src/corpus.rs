@@ -0,0 +1,313 @@
+//! Loading the seed inputs.
+
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use tree_sitter::{Language, Tree};
+
+use crate::{parse, read_file, Args};
+
+/// Find the files under `dir`, descending at most `max_depth` levels
+///
+/// Symbolic links to directories are not followed, which rules out loops.
+fn walk(dir: &Path, max_depth: Option<usize>, paths: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("When reading tests from {}", dir.display()))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if max_depth != Some(0) {
+                walk(&path, max_depth.map(|d| d - 1), paths)?;
+            }
+        } else if path.is_file() {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// The corpus files: those under `dir`, or those listed on stdin (one per
+/// line) if `dir` is `-`, with the extensions given in `args`
+///
+/// Listed files are not checked here, [`load`] warns about those it can't
+/// read.
+pub(crate) fn find(dir: &Path, args: &Args) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    if dir == Path::new("-") {
+        for line in io::stdin().lock().lines() {
+            let line = line.context("Failed to read corpus files from stdin")?;
+            if !line.trim().is_empty() {
+                paths.push(PathBuf::from(line));
+            }
+        }
+    } else {
+        walk(dir, args.max_depth, &mut paths)?;
+    }
+    if !args.extensions.is_empty() {
+        let found = paths.len();
+        paths.retain(|p| {
+            extension(p).is_some_and(|e| {
+                args.extensions
+                    .iter()
+                    .any(|x| e == x.trim_start_matches('.'))
+            })
+        });
+        info!(
+            "Accepted {} corpus files, filtered out {} by extension",
+            paths.len(),
+            found - paths.len()
+        );
+    }
+    Ok(paths)
+}
+
+/// The extension of `path`, ignoring a `.gz` that the `gzip` feature would
+/// decompress
+pub(crate) fn extension(path: &Path) -> Option<&OsStr> {
+    let ext = path.extension()?;
+    #[cfg(feature = "gzip")]
+    if ext == "gz" {
+        return Path::new(path.file_stem()?).extension();
+    }
+    Some(ext)
+}
+
+/// Read a corpus file, decompressing it if it's gzipped and the `gzip` feature
+/// is enabled
+fn read(path: &PathBuf) -> Result<Vec<u8>> {
+    let bytes = read_file(path)?;
+    #[cfg(feature = "gzip")]
+    if bytes.starts_with(&[0x1f, 0x8b]) {
+        use std::io::Read;
+        let mut out = Vec::new();
+        flate2::read::GzDecoder::new(bytes.as_slice())
+            .read_to_end(&mut out)
+            .with_context(|| format!("Failed to decompress {}", path.display()))?;
+        return Ok(out);
+    }
+    Ok(bytes)
+}
+
+/// Whether a tree is nothing but syntax errors
+///
+/// tree-sitter recovers from syntax errors with `ERROR` nodes rather than
+/// failing, and the rest of such a file is still worth splicing.
+fn all_errors(tree: &Tree) -> bool {
+    let root = tree.root_node();
+    let mut cursor = root.walk();
+    let all = root.children(&mut cursor).all(|n| n.is_error());
+    root.is_error() || (root.child_count() > 0 && all)
+}
+
+/// Read and parse files on up to `jobs` threads, returning results in order
+///
+/// The tree is `None` if the file couldn't be parsed, or it's nothing but
+/// syntax errors.
+fn read_and_parse(
+    language: Language,
+    paths: &[PathBuf],
+    jobs: usize,
+) -> Vec<Result<(Vec<u8>, Option<Tree>)>> {
+    let chunk_size = paths.len().div_ceil(jobs.max(1)).max(1);
+    std::thread::scope(|s| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || {
+                    chunk
+                        .iter()
+                        .map(|path| {
+                            let bytes = read(path)?;
+                            let tree = parse(language, &bytes).ok().filter(|t| !all_errors(t));
+                            Ok((bytes, tree))
+                        })
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap())
+            .collect()
+    })
+}
+
+/// Load the files from [`find`] for which `route` holds, according to the
+/// corpus options in `args`
+pub(crate) fn load(
+    language: Language,
+    paths: &[PathBuf],
+    args: &Args,
+    route: &dyn Fn(&Path) -> bool,
+) -> Result<HashMap<String, (Vec<u8>, Tree)>> {
+    let mut paths = paths.to_vec();
+    paths.retain(|p| route(p));
+
+    let loaded = read_and_parse(language, &paths, args.jobs);
+
+    let mut files = HashMap::new();
+    let mut hashes = HashSet::new();
+    let mut deduped = 0;
+    let mut unparsed = 0;
+    let mut unreadable = 0;
+    let mut with_errors = 0;
+    for (path, result) in paths.iter().zip(loaded) {
+        let (bytes, tree) = match result {
+            Err(e) => {
+                warn!("{e:#}");
+                unreadable += 1;
+                continue;
+            }
+            Ok((_, None)) => {
+                unparsed += 1;
+                continue;
+            }
+            Ok((bytes, Some(tree))) => (bytes, tree),
+        };
+        if tree.root_node().has_error() {
+            with_errors += 1;
+        }
+        if args.dedup_corpus {
+            // Strong enough that distinct files won't collide
+            if !hashes.insert(sha1_smol::Sha1::from(&bytes).digest().bytes()) {
+                deduped += 1;
+                continue;
+            }
+        }
+        files.insert(String::from(path.to_string_lossy()), (bytes, tree));
+    }
+    if args.dedup_corpus {
+        info!("Skipped {deduped} duplicate corpus files");
+    }
+    if unparsed > 0 {
+        warn!("Skipped {unparsed} corpus files that failed to parse");
+    }
+    if with_errors > 0 {
+        info!("Loaded {with_errors} corpus files despite syntax errors");
+    }
+    info!(
+        "Loaded {} corpus files, skipped {}",
+        files.len(),
+        unreadable + unparsed
+    );
+    Ok(files)
+}
+
+/// Load a file of `GLOB WEIGHT` lines, ignoring blank lines and `#` comments
+pub(crate) fn load_weights(path: &Path) -> Result<Vec<(glob::Pattern, u32)>> {
+    let bytes = read_file(&path.to_path_buf())?;
+    let mut weights = Vec::new();
+    for (i, line) in String::from_utf8_lossy(&bytes).lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let context = || format!("Malformed entry on line {} of {}", i + 1, path.display());
+        let (pattern, weight) = line
+            .rsplit_once(char::is_whitespace)
+            .with_context(context)?;
+        let pattern = glob::Pattern::new(pattern.trim_end()).with_context(context)?;
+        let weight = weight.parse().with_context(context)?;
+        weights.push((pattern, weight));
+    }
+    Ok(weights)
+}
+
+/// Weight of the first pattern that matches `key`, or 1
+pub(crate) fn weight(weights: &[(glob::Pattern, u32)], key: &str) -> u32 {
+    weights
+        .iter()
+        .find(|(pattern, _)| pattern.matches(key))
+        .map_or(1, |(_, weight)| *weight)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{args, corpus};
+
+    fn load_c(dir: &Path, flags: &[&str]) -> HashMap<String, (Vec<u8>, Tree)> {
+        let dir = dir.to_string_lossy();
+        let argv: Vec<&str> = flags
+            .iter()
+            .copied()
+            .chain([dir.as_ref(), "true"])
+            .collect();
+        let args = args(&argv);
+        let paths = find(Path::new(dir.as_ref()), &args).unwrap();
+        load(tree_sitter_c::language(), &paths, &args, &|_| true).unwrap()
+    }
+
+    #[test]
+    fn dedup_corpus() {
+        let dir = corpus(&[("a.c", "int x;"), ("b.c", "int x;"), ("c.c", "int y;")]);
+        assert_eq!(load_c(dir.path(), &[]).len(), 3);
+        assert_eq!(load_c(dir.path(), &["--dedup-corpus"]).len(), 2);
+    }
+
+    #[test]
+    fn jobs_load_the_same_files() {
+        let files: Vec<_> = (0..40)
+            .map(|i| {
+                (
+                    format!("{i}.c"),
+                    format!("int f{i}(int x) {{ return x * {i}; }}"),
+                )
+            })
+            .collect();
+        let files: Vec<_> = files
+            .iter()
+            .map(|(k, v)| (k.as_str(), v.as_str()))
+            .collect();
+        let dir = corpus(&files);
+        let loaded = |jobs| {
+            let mut loaded: Vec<_> = load_c(dir.path(), &["-j", jobs])
+                .into_iter()
+                .map(|(path, (bytes, tree))| (path, bytes, tree.root_node().to_sexp()))
+                .collect();
+            loaded.sort_unstable();
+            loaded
+        };
+        let serial = loaded("1");
+        assert_eq!(serial.len(), 40);
+        assert_eq!(serial, loaded("7"));
+    }
+
+    #[test]
+    fn syntax_errors_are_skipped() {
+        let dir = corpus(&[
+            ("good.c", "int x;"),
+            ("recovered.c", "))) int main(void) { return 0; }"),
+            ("bad.c", "int main( { return"),
+            ("worse.c", "}}}"),
+        ]);
+        let files = load_c(dir.path(), &[]);
+        let mut keys: Vec<_> = files
+            .keys()
+            .map(|k| &k[k.rfind('/').unwrap() + 1..])
+            .collect();
+        keys.sort_unstable();
+        assert_eq!(keys, ["good.c", "recovered.c"]);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn gzipped_files() {
+        use std::io::Write;
+
+        let dir = corpus(&[("plain.c", "int y;")]);
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(b"int x;").unwrap();
+        fs::write(dir.path().join("packed.c.gz"), gz.finish().unwrap()).unwrap();
+        let files = load_c(dir.path(), &["--extensions", "c"]);
+        assert_eq!(files.len(), 2);
+        let (_, (bytes, _)) = files.iter().find(|(k, _)| k.ends_with(".gz")).unwrap();
+        assert_eq!(bytes, b"int x;");
+    }
+}
@@ -0,0 +1,55 @@
+//! Coverage feedback from a user-supplied command.
+
+use std::collections::HashSet;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Coverage points seen so far by one fuzzing thread
+///
+/// After each execution of the target, `cmd` is run with `sh -c` and each line
+/// of its stdout counts as one coverage point, e.g., an edge ID or a
+/// `file:line` pair extracted from the target's coverage files.
+#[derive(Debug)]
+pub(crate) struct Coverage {
+    cmd: String,
+    cwd: Option<PathBuf>,
+    seen: HashSet<Vec<u8>>,
+}
+
+impl Coverage {
+    pub(crate) fn new(cmd: String, cwd: Option<PathBuf>) -> Self {
+        Coverage {
+            cmd,
+            cwd,
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Run the coverage command, returning whether it reported anything new
+    pub(crate) fn record(&mut self) -> io::Result<bool> {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(&self.cmd)
+            .stdin(Stdio::null())
+            .stderr(Stdio::inherit());
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        let output = cmd.output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "coverage command exited with {}",
+                output.status
+            )));
+        }
+        let mut new = false;
+        for line in output.stdout.split(|b| *b == b'\n') {
+            if !line.is_empty() && !self.seen.contains(line) {
+                self.seen.insert(line.to_vec());
+                new = true;
+            }
+        }
+        Ok(new)
+    }
+}
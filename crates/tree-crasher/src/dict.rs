@@ -0,0 +1,103 @@
+//! Splicing tokens from a user-supplied dictionary into mutants.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use rand::Rng;
+use tree_sitter::Language;
+
+use crate::{nodes, parse, read_file};
+
+/// Percent of mutants that get a dictionary token
+const INJECT_PERCENT: u32 = 10;
+
+/// Decode the quoted part of a dictionary entry
+fn unescape(quoted: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(quoted.len());
+    let mut rest = quoted.bytes();
+    while let Some(b) = rest.next() {
+        if b != b'\\' {
+            bytes.push(b);
+            continue;
+        }
+        match rest.next()? {
+            b'x' => {
+                let hex = [rest.next()?, rest.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            c => bytes.push(c),
+        }
+    }
+    Some(bytes)
+}
+
+/// Load an AFL-style dictionary
+///
+/// Each non-empty line that isn't a `#` comment holds a token in double
+/// quotes, optionally preceded by a name and `=`, e.g., `kw_proto="__proto__"`.
+/// Within the quotes, `\\`, `\"`, and `\xNN` escapes are understood.
+pub(crate) fn load(path: &Path) -> Result<Vec<Vec<u8>>> {
+    let bytes = read_file(&path.to_path_buf())?;
+    let text = String::from_utf8_lossy(&bytes);
+    let mut tokens = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let token = line
+            .find('"')
+            .filter(|start| line.len() > start + 1 && line.ends_with('"'))
+            .and_then(|start| unescape(&line[start + 1..line.len() - 1]))
+            .with_context(|| format!("Malformed entry on line {} of {}", i + 1, path.display()))?;
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Sometimes replace a random leaf node of `mutant` with a random token
+pub(crate) fn maybe_inject(
+    language: Language,
+    rng: &mut impl Rng,
+    tokens: &[Vec<u8>],
+    mutant: Vec<u8>,
+    max_size: usize,
+) -> Vec<u8> {
+    if tokens.is_empty() || rng.gen_range(0..100) >= INJECT_PERCENT {
+        return mutant;
+    }
+    inject(language, rng, tokens, &mutant, max_size).unwrap_or(mutant)
+}
+
+/// Replace a random leaf node of `mutant` with a random token
+///
+/// Returns `None` if the mutant has no leaves to replace, or if the result
+/// would exceed `max_size`.
+fn inject(
+    language: Language,
+    rng: &mut impl Rng,
+    tokens: &[Vec<u8>],
+    mutant: &[u8],
+    max_size: usize,
+) -> Option<Vec<u8>> {
+    let tree = parse(language, mutant).ok()?;
+    let leaves: Vec<_> = nodes(&tree)
+        .into_iter()
+        .filter(|n| n.child_count() == 0 && n.start_byte() < n.end_byte())
+        .map(|n| n.byte_range())
+        .collect();
+    if leaves.is_empty() {
+        return None;
+    }
+    let range = &leaves[rng.gen_range(0..leaves.len())];
+    let token = &tokens[rng.gen_range(0..tokens.len())];
+    if mutant.len() - range.len() + token.len() > max_size {
+        return None;
+    }
+    let mut out = Vec::with_capacity(mutant.len() - range.len() + token.len());
+    out.extend_from_slice(&mutant[..range.start]);
+    out.extend_from_slice(token);
+    out.extend_from_slice(&mutant[range.end..]);
+    Some(out)
+}
@@ -0,0 +1,64 @@
+//! Loading a tree-sitter grammar at runtime.
+
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use tree_sitter::Language;
+
+use crate::{read_file, Args};
+
+/// Guess the name of a grammar from the file name of its library, e.g.,
+/// `libtree-sitter-c_sharp.so` becomes `c_sharp`
+fn guess_name(lib: &Path) -> String {
+    let stem = lib.file_stem().unwrap_or_default().to_string_lossy();
+    let stem = stem.strip_prefix("lib").unwrap_or(&stem);
+    let stem = stem.strip_prefix("tree-sitter-").unwrap_or(stem);
+    stem.replace('-', "_")
+}
+
+/// Load the grammar given by `--grammar` and its node types, making sure both
+/// are usable
+pub(crate) fn load(args: &Args) -> Result<(Language, String)> {
+    let lib_path = args.grammar.as_ref().context("--grammar is required")?;
+    let node_types_path = args
+        .node_types
+        .as_ref()
+        .context("--node-types is required")?;
+    let name = match &args.grammar_name {
+        Some(name) => name.clone(),
+        None => guess_name(lib_path),
+    };
+
+    let symbol = format!("tree_sitter_{name}");
+    // SAFETY: Loading a library runs its initializers. That's the price of
+    // admission for using a grammar that the user chose.
+    let lib = unsafe { libloading::Library::new(lib_path) }
+        .with_context(|| format!("Failed to load grammar {}", lib_path.display()))?;
+    // SAFETY: tree-sitter grammars export a function of this type under this
+    // name.
+    let language = unsafe {
+        let get = lib
+            .get::<unsafe extern "C" fn() -> Language>(symbol.as_bytes())
+            .with_context(|| {
+                format!(
+                    "Grammar {} has no function {symbol}, try --grammar-name",
+                    lib_path.display()
+                )
+            })?;
+        get()
+    };
+    // The language points into the library, so it must stay loaded
+    std::mem::forget(lib);
+    tree_sitter::Parser::new()
+        .set_language(language)
+        .with_context(|| format!("Grammar {} can't be used", lib_path.display()))?;
+
+    let bytes = read_file(node_types_path)?;
+    let json = String::from_utf8(bytes)
+        .with_context(|| format!("{} is not UTF-8", node_types_path.display()))?;
+    treereduce::NodeTypes::new(&json)
+        .with_context(|| format!("Invalid node types in {}", node_types_path.display()))?;
+    tree_splicer::node_types::NodeTypes::new(&json)
+        .with_context(|| format!("Invalid node types in {}", node_types_path.display()))?;
+    Ok((language, json))
+}
@@ -0,0 +1,37 @@
+//! Cheap, instrumentation-free feedback heuristics.
+
+use std::time::Duration;
+
+/// Don't call anything rare until this many executions have been recorded
+const WARMUP: u64 = 1000;
+
+/// Buckets holding less than this percent of executions are rare
+const RARE_PERCENT: u64 = 1;
+
+/// Histogram of target execution times with logarithmically-sized buckets
+///
+/// Inputs with unusual runtimes often exercise different code in the target,
+/// so they make good seeds.
+#[derive(Debug)]
+pub(crate) struct TimeBuckets {
+    counts: [u64; 65],
+    total: u64,
+}
+
+impl TimeBuckets {
+    pub(crate) fn new() -> Self {
+        TimeBuckets {
+            counts: [0; 65],
+            total: 0,
+        }
+    }
+
+    /// Record an execution time, returning whether its bucket is rare
+    pub(crate) fn record(&mut self, elapsed: Duration) -> bool {
+        let micros = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        let bucket = usize::try_from(u64::BITS - micros.leading_zeros()).unwrap_or_default();
+        self.counts[bucket] += 1;
+        self.total += 1;
+        self.total > WARMUP && self.counts[bucket] * 100 < self.total * RARE_PERCENT
+    }
+}
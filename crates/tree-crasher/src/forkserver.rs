@@ -0,0 +1,369 @@
+//! Running AFL-instrumented targets through their forkserver.
+//!
+//! This speaks the classic AFL protocol:
+//!
+//! 1. The target gets the ID of a System V shared memory segment of
+//!    [`MAP_SIZE`] bytes in `__AFL_SHM_ID`, where it counts edge hits, along
+//!    with a control pipe on fd 198 and a status pipe on fd 199.
+//! 2. Before `main`, the target's forkserver writes 4 bytes to the status pipe
+//!    to say that it's ready.
+//! 3. For each input, we write 4 bytes to the control pipe (nonzero if we had
+//!    to kill the previous child), and the forkserver forks. It writes the
+//!    child's PID to the status pipe, and then its `waitpid` status once the
+//!    child exits.
+//!
+//! The input is written to a file, which the target gets as its stdin or in
+//! place of `@@`. `AFL_OLD_FORKSERVER` is set so that newer versions of AFL++
+//! use this protocol too.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, ExitStatus};
+
+use crate::check::{CmdCheck, InputFile, Outcome, Output};
+use crate::persistent::{poll, Server};
+
+/// Size of the coverage map
+pub(crate) const MAP_SIZE: usize = 1 << 16;
+
+const CONTROL_FD: libc::c_int = 198;
+const STATUS_FD: libc::c_int = 199;
+
+/// Map a hit count to the bucket that AFL would put it in, as one bit
+fn bucket(hits: u8) -> u8 {
+    match hits {
+        0 => 0,
+        1 => 1,
+        2 => 2,
+        3 => 4,
+        4..=7 => 8,
+        8..=15 => 16,
+        16..=31 => 32,
+        32..=127 => 64,
+        128.. => 128,
+    }
+}
+
+fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    // SAFETY: `fds` has room for the two descriptors.
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: pipe2 just opened these, and nothing else owns them.
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+/// A System V shared memory segment, removed on drop
+#[derive(Debug)]
+struct SharedMap {
+    id: libc::c_int,
+    ptr: *mut u8,
+}
+
+impl SharedMap {
+    fn new() -> io::Result<Self> {
+        // SAFETY: No pointers are involved.
+        let id = unsafe {
+            libc::shmget(
+                libc::IPC_PRIVATE,
+                MAP_SIZE,
+                libc::IPC_CREAT | libc::IPC_EXCL | 0o600,
+            )
+        };
+        if id < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `id` is a segment we just created.
+        let ptr = unsafe { libc::shmat(id, std::ptr::null(), 0) };
+        if ptr as isize == -1 {
+            let e = io::Error::last_os_error();
+            // SAFETY: As above.
+            unsafe { libc::shmctl(id, libc::IPC_RMID, std::ptr::null_mut()) };
+            return Err(e);
+        }
+        Ok(SharedMap {
+            id,
+            ptr: ptr.cast(),
+        })
+    }
+
+    fn bytes(&mut self) -> &mut [u8] {
+        // SAFETY: The segment is MAP_SIZE bytes and stays attached until drop.
+        // The target only writes to it while we wait for the status pipe.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr, MAP_SIZE) }
+    }
+}
+
+impl Drop for SharedMap {
+    fn drop(&mut self) {
+        // SAFETY: `ptr` and `id` came from shmat and shmget.
+        unsafe {
+            libc::shmdt(self.ptr.cast());
+            libc::shmctl(self.id, libc::IPC_RMID, std::ptr::null_mut());
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Process {
+    child: Child,
+    control: File,
+    status: File,
+    /// We killed the last child for timing out
+    killed: bool,
+}
+
+/// An AFL forkserver, restarted if it dies
+#[derive(Debug)]
+pub(crate) struct ForkServer<'a> {
+    chk: &'a CmdCheck,
+    map: SharedMap,
+    /// Buckets of hit counts seen so far, for each edge
+    seen: Vec<u8>,
+    new_coverage: bool,
+    input: InputFile,
+    args: Vec<String>,
+    process: Option<Process>,
+}
+
+impl<'a> ForkServer<'a> {
+    pub(crate) fn new(chk: &'a CmdCheck) -> io::Result<Self> {
+        let (input, args) = chk.forkserver_input()?;
+        Ok(ForkServer {
+            chk,
+            map: SharedMap::new()?,
+            seen: vec![0; MAP_SIZE],
+            new_coverage: false,
+            input,
+            args,
+            process: None,
+        })
+    }
+
+    fn spawn(&self) -> io::Result<Process> {
+        let (control_read, control_write) = pipe()?;
+        let (status_read, status_write) = pipe()?;
+        let mut cmd = self
+            .chk
+            .forkserver_command(self.args.clone(), &self.input.file)?;
+        cmd.env("__AFL_SHM_ID", self.map.id.to_string())
+            .env("AFL_MAP_SIZE", MAP_SIZE.to_string())
+            .env("AFL_OLD_FORKSERVER", "1");
+        let (control, status) = (control_read.as_raw_fd(), status_write.as_raw_fd());
+        // SAFETY: dup2 is async-signal-safe, and clears FD_CLOEXEC on the
+        // copies.
+        unsafe {
+            cmd.pre_exec(move || {
+                if libc::dup2(control, CONTROL_FD) < 0 || libc::dup2(status, STATUS_FD) < 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = cmd.spawn()?;
+        drop((control_read, status_write));
+        let mut process = Process {
+            child,
+            control: File::from(control_write),
+            status: File::from(status_read),
+            killed: false,
+        };
+        if !poll(&process.status, self.chk.timeout())? {
+            process.child.kill()?;
+            process.child.wait()?;
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "forkserver didn't start in time",
+            ));
+        }
+        let mut hello = [0u8; 4];
+        if let Err(e) = process.status.read_exact(&mut hello) {
+            let status = process.child.wait()?;
+            return Err(io::Error::new(
+                e.kind(),
+                format!("forkserver didn't start ({status}), is the target instrumented?"),
+            ));
+        }
+        Ok(process)
+    }
+
+    /// Run the current input in a fresh child, returning `None` on timeout
+    fn fork(&mut self) -> io::Result<Option<ExitStatus>> {
+        if self.process.is_none() {
+            self.process = Some(self.spawn()?);
+        }
+        let process = self.process.as_mut().unwrap();
+        process
+            .control
+            .write_all(&u32::from(process.killed).to_ne_bytes())?;
+        let mut pid = [0u8; 4];
+        process.status.read_exact(&mut pid)?;
+        let pid = i32::from_ne_bytes(pid);
+        process.killed = !poll(&process.status, self.chk.timeout())?;
+        if process.killed {
+            // SAFETY: No pointers are involved.
+            unsafe { libc::kill(pid, libc::SIGKILL) };
+        }
+        let mut status = [0u8; 4];
+        process.status.read_exact(&mut status)?;
+        if process.killed {
+            return Ok(None);
+        }
+        Ok(Some(ExitStatus::from_raw(i32::from_ne_bytes(status))))
+    }
+}
+
+impl Server for ForkServer<'_> {
+    fn run(&mut self, input: &[u8]) -> io::Result<Outcome> {
+        let file = self.input.file.as_file_mut();
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(input)?;
+        file.rewind()?;
+        self.map.bytes().fill(0);
+        let status = match self.fork() {
+            Ok(s) => s,
+            Err(e) => {
+                // Start over with a new forkserver next time
+                if let Some(mut process) = self.process.take() {
+                    process.child.kill().ok();
+                    process.child.wait().ok();
+                }
+                return Err(e);
+            }
+        };
+        self.new_coverage = false;
+        for (hits, seen) in self.map.bytes().iter().zip(&mut self.seen) {
+            let b = bucket(*hits);
+            if b & !*seen != 0 {
+                *seen |= b;
+                self.new_coverage = true;
+            }
+        }
+        Ok(match status {
+            None => Outcome::Timeout,
+            Some(s) => self.chk.classify(Some(s), &Output::default()),
+        })
+    }
+
+    fn new_coverage(&self) -> bool {
+        self.new_coverage
+    }
+}
+
+impl Drop for ForkServer<'_> {
+    fn drop(&mut self) {
+        if let Some(mut process) = self.process.take() {
+            process.child.kill().ok();
+            process.child.wait().ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::testing::compile;
+
+    /// A forkserver as AFL's instrumentation would run it, that counts a hit
+    /// on edge 0 for every input and on edge 1 for inputs with a `b`, aborts
+    /// on `!`, and hangs on `~`
+    const TARGET: &str = r#"
+#include <stdlib.h>
+#include <string.h>
+#include <sys/shm.h>
+#include <sys/wait.h>
+#include <unistd.h>
+
+int main(void) {
+    unsigned char *map = shmat(atoi(getenv("__AFL_SHM_ID")), NULL, 0);
+    int hello = 0;
+    if (map == (void *)-1 || write(199, &hello, 4) != 4) {
+        return 2;
+    }
+    int killed;
+    while (read(198, &killed, 4) == 4) {
+        pid_t pid = fork();
+        if (pid == 0) {
+            close(198);
+            close(199);
+            char buf[4096];
+            ssize_t len = read(0, buf, sizeof buf);
+            map[0]++;
+            if (len > 0 && memchr(buf, 'b', len)) {
+                map[1]++;
+            }
+            if (len > 0 && memchr(buf, '!', len)) {
+                abort();
+            }
+            while (len > 0 && memchr(buf, '~', len)) {
+                pause();
+            }
+            return 0;
+        }
+        int status;
+        if (write(199, &pid, 4) != 4 || waitpid(pid, &status, 0) < 0 ||
+            write(199, &status, 4) != 4) {
+            return 2;
+        }
+    }
+    return 0;
+}
+"#;
+
+    #[test]
+    fn buckets() {
+        let expected = [
+            (0, 0),
+            (1, 1),
+            (2, 2),
+            (3, 4),
+            (7, 8),
+            (8, 16),
+            (31, 32),
+            (32, 64),
+            (127, 64),
+            (128, 128),
+            (255, 128),
+        ];
+        for (hits, b) in expected {
+            assert_eq!(bucket(hits), b, "{hits} hits");
+        }
+        assert!((0..=255).all(|h| bucket(h).count_ones() == u32::from(h != 0)));
+    }
+
+    #[test]
+    fn forkserver() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = compile(dir.path(), "forkserver", TARGET);
+        let chk = CmdCheck::new(
+            exe.to_string_lossy().into_owned(),
+            vec![],
+            vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+            false,
+            Some(Duration::from_millis(500)),
+        );
+        let mut server = ForkServer::new(&chk).unwrap();
+        assert_eq!(server.run(b"a").unwrap(), Outcome::Boring);
+        assert!(server.new_coverage());
+        assert_eq!(server.run(b"aa").unwrap(), Outcome::Boring);
+        assert!(!server.new_coverage());
+        assert_eq!(server.run(b"ab").unwrap(), Outcome::Boring);
+        assert!(server.new_coverage());
+        assert_eq!(server.run(b"a!").unwrap(), Outcome::Signal(libc::SIGABRT));
+        assert_eq!(server.run(b"a~").unwrap(), Outcome::Timeout);
+        assert_eq!(server.run(b"ab").unwrap(), Outcome::Boring);
+        assert!(!server.new_coverage());
+    }
+}
@@ -0,0 +1,55 @@
+//! Distributions of input sizes, for the statistics.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+const BUCKETS: usize = usize::BITS as usize + 1;
+
+/// Number of inputs with sizes in `min..=max` bytes
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize)]
+pub struct SizeBucket {
+    pub min: usize,
+    pub max: usize,
+    pub count: u64,
+}
+
+/// Counts of input sizes in power-of-two buckets, shared between threads
+#[derive(Debug)]
+pub(crate) struct Histogram {
+    counts: [AtomicU64; BUCKETS],
+}
+
+impl Histogram {
+    pub(crate) fn new() -> Self {
+        Histogram {
+            counts: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    pub(crate) fn record(&self, size: usize) {
+        let i = usize::try_from(usize::BITS - size.leading_zeros()).unwrap_or(BUCKETS - 1);
+        self.counts[i].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The buckets that aren't empty, from smallest to largest
+    pub(crate) fn buckets(&self) -> Vec<SizeBucket> {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter_map(|(i, count)| {
+                let count = count.load(Ordering::Relaxed);
+                if count == 0 {
+                    return None;
+                }
+                // Bucket i holds sizes with i significant bits
+                let (min, max) = match i {
+                    0 => (0, 0),
+                    _ => {
+                        let min = 1 << (i - 1);
+                        (min, min - 1 + min)
+                    }
+                };
+                Some(SizeBucket { min, max, count })
+            })
+            .collect()
+    }
+}
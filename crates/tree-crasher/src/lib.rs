@@ -1,42 +1,130 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::process::ExitStatusExt;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Duration;
 use std::time::Instant;
+
 use anyhow::{Context, Result};
-use clap::Parser;
 use clap_verbosity_flag::{InfoLevel, Verbosity};
-use rand::Rng;
-use regex::Regex;
+use log::{debug, error, info, warn};
+use rand::distributions::WeightedIndex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use regex::RegexBuilder;
 use tree_sitter::Language;
 use tree_sitter::Tree;
-use tree_splicer::splice::{Config, Splicer};
-use treereduce::Check;
-use treereduce::CmdCheck;
+use tree_splicer::splice::Config;
 use uuid::Uuid;
 
+pub use histogram::SizeBucket;
+
+mod check;
+mod config;
+mod corpus;
+#[cfg(feature = "coverage")]
+mod coverage;
+mod dict;
+mod dynamic;
+mod feedback;
+#[cfg(feature = "forkserver")]
+mod forkserver;
+mod histogram;
+mod logger;
+mod metadata;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod minimize;
+mod mutator;
+mod persistent;
+mod signature;
+mod splice;
+#[cfg(test)]
+mod testing;
+
+use check::{CmdCheck, Outcome, Output};
+use feedback::TimeBuckets;
+use metadata::{Metadata, Source};
+use signature::{signature, Signatures};
+use splice::Splicer;
+
 /// An easy-to-use grammar-based black-box fuzzer
 #[derive(Clone, Debug, clap::Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(author, version, about, long_about = None, subcommand_negates_reqs = true)]
 pub struct Args {
-    /// Percent of "chaotic" mutations - may introduce syntax errors
-    #[arg(help_heading = "Mutation options", short, long, default_value_t = 5)]
-    pub chaos: u8,
+    #[command(subcommand)]
+    pub command: Option<Command>,
 
-    /// Percent of deletion mutations - the rest are splices
-    #[arg(help_heading = "Mutation options", long, default_value_t = 5)]
-    pub deletions: u8,
+    /// Percent of "chaotic" mutations - may introduce syntax errors; random if unset
+    #[arg(help_heading = "Mutation options", short, long, value_name = "CHAOS")]
+    pub chaos: Option<u8>,
+
+    /// Percent of deletion mutations - the rest are splices; random if unset
+    #[arg(help_heading = "Mutation options", long, value_name = "DELETIONS")]
+    pub deletions: Option<u8>,
 
     /// Approximate maximum file size to produce (bytes); default = 1MiB
     #[arg(help_heading = "Mutation options", long, default_value_t = 1048576)]
     pub max_size: usize,
 
-    /// Number of mutations per test
-    #[arg(help_heading = "Mutation options", short, long, default_value_t = 16)]
-    pub mutations: usize,
+    /// Number of mutations per test; random if unset
+    #[arg(
+        help_heading = "Mutation options",
+        short,
+        long,
+        value_name = "MUTATIONS"
+    )]
+    pub mutations: Option<usize>,
+
+    /// Pick random mutation options for each batch, even if given explicitly
+    #[arg(help_heading = "Mutation options", long)]
+    pub randomize_mutations: bool,
+
+    /// Number of tests to generate before picking new mutation options;
+    /// smaller batches re-randomize the options more often
+    #[arg(
+        help_heading = "Mutation options",
+        long,
+        default_value_t = 100_000,
+        value_name = "N"
+    )]
+    pub batch: usize,
+
+    /// Add inputs with unusual target execution times to the corpus
+    #[arg(help_heading = "Mutation options", long)]
+    pub time_feedback: bool,
+
+    /// File of `GLOB WEIGHT` lines; corpus files whose paths match a glob are
+    /// picked for mutation that much more often (the first match counts, and
+    /// the default weight is 1)
+    #[arg(help_heading = "Mutation options", long, value_name = "FILE")]
+    pub weights: Option<PathBuf>,
+
+    /// Re-parse each mutant after this many splices; never if unset. More
+    /// frequent re-parsing keeps mutants syntactically valid more often, but
+    /// makes generating them slower
+    #[arg(help_heading = "Mutation options", long, value_name = "N")]
+    pub reparse: Option<usize>,
 
-    /// Use Radamsa for mutations; ignore all other mutation options
+    /// AFL-style dictionary of tokens to splice into some mutants
+    #[arg(help_heading = "Mutation options", long, value_name = "FILE")]
+    pub dict: Option<PathBuf>,
+
+    /// Shell command to run after each test; inputs that make it print a line
+    /// it hasn't printed before are added to the corpus
+    #[cfg(feature = "coverage")]
+    #[arg(help_heading = "Mutation options", long, value_name = "CMD")]
+    pub coverage_cmd: Option<String>,
+
+    /// Use Radamsa for mutations; ignore other mutation options but --max-size
     #[cfg(feature = "radamsa")]
     #[arg(help_heading = "Mutation options", short, long)]
     pub radamsa: bool,
@@ -45,11 +133,64 @@ pub struct Args {
     #[arg(short, long)]
     pub debug: bool,
 
+    /// Exit with code 1 if any crashes were found
+    #[arg(long)]
+    pub fail_on_crash: bool,
+
+    /// Shared library with the tree-sitter grammar to use (only for
+    /// tree-crasher-dynamic)
+    #[arg(long, value_name = "LIB")]
+    pub grammar: Option<PathBuf>,
+
+    /// Name of the grammar in the tree_sitter_<NAME> function exported by
+    /// --grammar; guessed from the file name if unset
+    #[arg(long, value_name = "NAME", requires = "grammar")]
+    pub grammar_name: Option<String>,
+
+    /// The grammar's node-types.json (only for tree-crasher-dynamic)
+    #[arg(long, value_name = "FILE")]
+    pub node_types: Option<PathBuf>,
+
+    /// TOML file with default values for options, keyed by their names with
+    /// underscores, e.g., `max_size = 4096`
+    #[arg(long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Run the target on this file, explain whether it's interesting, and exit
+    #[arg(long, value_name = "FILE")]
+    pub explain: Option<PathBuf>,
+
+    /// Print a single mutant to stdout and exit without running the target
+    #[arg(long)]
+    pub print_mutant: bool,
+
+    /// Write mutants to the output directory without running the target, to
+    /// check what a grammar and corpus produce
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Number of mutants to write with --dry-run
+    #[arg(long, default_value_t = 100, value_name = "N", requires = "dry_run")]
+    pub dry_run_count: usize,
+
     /// Exit code to consider interesting
     #[arg(help_heading = "Interestingness check options",
           long, default_values_t = Vec::<i32>::new(), value_name = "CODE")]
     interesting_exit_code: Vec<i32>,
 
+    /// Don't consider deaths by this signal interesting
+    #[arg(help_heading = "Interestingness check options", long, value_name = "N")]
+    pub ignore_signal: Vec<i32>,
+
+    /// Consider every nonzero exit code interesting
+    #[arg(help_heading = "Interestingness check options", long)]
+    pub any_nonzero_exit: bool,
+
+    /// Don't consider exit codes 128-255 interesting unless given with
+    /// --interesting-exit-code; the target being killed by a signal still is
+    #[arg(help_heading = "Interestingness check options", long)]
+    pub no_default_signal_codes: bool,
+
     /// Regex to match interesting stdout
     #[arg(
         help_heading = "Interestingness check options",
@@ -84,15 +225,207 @@ pub struct Args {
     )]
     uninteresting_stderr: Option<String>,
 
+    /// Regex to match interesting output, i.e., stdout followed by stderr, for
+    /// patterns that span both; any of the interesting regexes matching is
+    /// enough
+    #[arg(
+        help_heading = "Interestingness check options",
+        long,
+        value_name = "REGEX"
+    )]
+    interesting_output: Option<String>,
+
+    /// Regex to match *uninteresting* output (stdout followed by stderr),
+    /// overrides all interesting regexes
+    #[arg(
+        help_heading = "Interestingness check options",
+        long,
+        value_name = "REGEX",
+        requires = "interesting_output"
+    )]
+    uninteresting_output: Option<String>,
+
+    /// Consider runs that print more than this many bytes interesting; not
+    /// with --debug, which shows the output rather than capturing it
+    #[arg(
+        help_heading = "Interestingness check options",
+        long,
+        value_name = "N",
+        conflicts_with = "debug"
+    )]
+    pub interesting_output_bytes: Option<usize>,
+
+    /// Keep at most this many bytes of the target's stdout and of its stderr,
+    /// for matching and saving
+    #[arg(long, value_name = "N", default_value_t = 4 * 1024 * 1024)]
+    pub max_output_bytes: usize,
+
+    /// How to tell whether two crashes are the same, to save only one of them
+    #[arg(long, value_enum, default_value_t = Dedup::Off)]
+    pub dedup: Dedup,
+
+    /// Additional regex to match interesting stderr, alongside the default
+    /// (or --interesting-stderr)
+    #[arg(
+        help_heading = "Interestingness check options",
+        long,
+        value_name = "REGEX"
+    )]
+    pub also_interesting_stderr: Vec<String>,
+
+    /// Additional regex to match *uninteresting* stderr, alongside the default
+    /// (or --uninteresting-stderr)
+    #[arg(
+        help_heading = "Interestingness check options",
+        long,
+        value_name = "REGEX"
+    )]
+    pub also_uninteresting_stderr: Vec<String>,
+
+    /// Match the stdout and stderr regexes case-insensitively
+    #[arg(help_heading = "Interestingness check options", long)]
+    pub regex_case_insensitive: bool,
+
+    /// Let ^ and $ in the stdout and stderr regexes match at line boundaries
+    #[arg(help_heading = "Interestingness check options", long)]
+    pub regex_multiline: bool,
+
+    /// Compute crash signatures from the output on the reduced input, at the
+    /// cost of reducing repeats of known crashes
+    #[arg(long)]
+    pub normalize_crashes_before_dedup: bool,
+
+    /// Maximum number of crash signatures to remember (0 for unbounded)
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub max_signatures: usize,
+
+    /// Stop after running this many tests (0 for unlimited)
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub runs: u64,
+
+    /// Stop after saving this many crashes (0 for unlimited)
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub max_crashes: u64,
+
+    /// Stop after fuzzing for this many seconds (0 for unlimited)
+    #[arg(long, default_value_t = 0, value_name = "SECONDS")]
+    pub max_total_time: u64,
+
+    /// Run tests at most this many times per second, across all threads (0
+    /// for unlimited)
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    pub max_execs_per_sec: u64,
+
+    /// Don't run the target on a test that the same thread ran recently
+    #[arg(long)]
+    pub skip_seen: bool,
+
+    /// Save crashing inputs as they are, without reducing them
+    #[arg(long, conflicts_with_all = ["reduce_passes", "reduce_jobs"])]
+    pub no_reduce: bool,
+
+    /// Which inputs to save for each crash
+    #[arg(long, value_enum, default_value_t = Keep::All)]
+    pub keep: Keep,
+
+    /// The order of the corpus files that seeds are picked from
+    #[arg(long, value_enum, default_value_t = CorpusOrder::Hash)]
+    pub corpus_order: CorpusOrder,
+
+    /// Number of passes when reducing crashes (0 to skip reduction)
+    #[arg(long, value_name = "N")]
+    pub reduce_passes: Option<usize>,
+
+    /// Number of threads for reducing each crash; these compete with the
+    /// fuzzing threads (--jobs) for CPU
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    pub reduce_jobs: usize,
+
+    /// Timeout (ms) for running the target while reducing crashes, for those
+    /// that are slow to show up [default: --timeout]
+    #[arg(long, value_name = "MS")]
+    pub reduce_timeout: Option<u64>,
+
+    /// Smallest change in size, in bytes, that reduction still tries to make;
+    /// lower is slower but gives smaller crashes
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 2,
+        value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub reduce_min_step: usize,
+
+    /// Don't delete nodes that the grammar requires when reducing crashes,
+    /// for grammars where that makes reduction go astray
+    #[arg(long)]
+    pub reduce_keep_required: bool,
+
+    /// Let reduction replace nodes of this kind with this text, e.g.,
+    /// `expression=0`; may be given more than once
+    #[arg(long, value_name = "KIND=TEXT", value_parser = parse_replacement)]
+    pub reduce_replace: Vec<(String, String)>,
+
+    /// Format of the statistics printed every few seconds
+    #[arg(long, value_enum, default_value_t = StatsFormat::Human)]
+    pub stats_format: StatsFormat,
+
+    /// Write statistics to this file instead of stdout
+    #[arg(long, value_name = "FILE")]
+    pub stats_file: Option<PathBuf>,
+
+    /// Serve statistics in the Prometheus format at http://HOST:PORT/metrics
+    #[cfg(feature = "metrics")]
+    #[arg(long, value_name = "HOST:PORT")]
+    pub metrics_addr: Option<String>,
+
     /// Number of threads
     #[arg(short, long, default_value_t = num_cpus::get())]
     pub jobs: usize,
 
+    /// Pin each thread to its own CPU, in order (Linux only)
+    #[arg(long)]
+    pub pin_cpus: bool,
+
     /// Directory to output to
     #[arg(short, long, default_value_os = "tree-crasher.out")]
     pub output: PathBuf,
 
-    /// Seed
+    /// Also save inputs that get added to the corpus (e.g., by
+    /// --time-feedback) here, named by the SHA-1 of their contents like in a
+    /// libFuzzer corpus
+    #[arg(long, value_name = "DIR")]
+    pub export_corpus: Option<PathBuf>,
+
+    /// Save artifacts directly in the output directory, rather than in a
+    /// subdirectory per thread
+    #[arg(long)]
+    pub flat: bool,
+
+    /// How to arrange crashes in the output directory
+    #[arg(long, value_enum, default_value_t = Layout::Flat)]
+    pub layout: Layout,
+
+    /// Save a shell script that reproduces each crash
+    #[arg(long)]
+    pub emit_repro: bool,
+
+    /// Shell command to run in the background for each new crash, with the
+    /// path of the saved input as $1 and the signal (if any) as $2
+    #[arg(long, value_name = "CMD")]
+    pub on_crash: Option<String>,
+
+    /// How to name crash and hang files
+    #[arg(long, value_enum, default_value_t = NameScheme::Uuid)]
+    pub name_scheme: NameScheme,
+
+    /// Shard identifier, included in crash filenames along with the hostname
+    #[arg(long, value_name = "ID")]
+    pub shard: Option<String>,
+
+    /// Seed for the random choices of each thread, e.g., which corpus files
+    /// to mutate and with which settings; the mutants themselves can still
+    /// differ between runs (see the docs)
     #[arg(short, long, default_value_t = 0)]
     pub seed: u64,
 
@@ -100,23 +433,310 @@ pub struct Args {
     #[arg(long, default_value_t = 500)]
     pub timeout: u64,
 
+    /// Run many inputs through each target process, see the documentation
+    /// for the protocol
+    #[arg(long, conflicts_with_all = ["interesting_stdout", "emit_repro"])]
+    pub persistent: bool,
+
+    /// Run an AFL-instrumented target through its forkserver, and save
+    /// inputs that reach new edges as seeds
+    #[cfg(feature = "forkserver")]
+    #[arg(long, conflicts_with_all = ["persistent", "emit_repro"])]
+    pub forkserver: bool,
+
+    /// With --persistent, restart the target after this many inputs
+    #[arg(long, default_value_t = 10_000, value_name = "N")]
+    pub persistent_runs: usize,
+
+    /// Warn when a thread hasn't finished a test (including reducing any
+    /// crash it found) in this long (s)
+    #[arg(long, value_name = "SECS")]
+    pub watchdog_timeout: Option<u64>,
+
+    /// Don't report crashes whose signatures match those saved in the output
+    /// directory by earlier runs (see --dedup)
+    #[arg(long)]
+    pub resume: bool,
+
+    /// Add to an output directory that already has files in it
+    #[arg(long)]
+    pub force: bool,
+
+    /// Save inputs that exceed the timeout as hang-*.out
+    #[arg(long)]
+    pub report_hangs: bool,
+
+    /// Save inputs that get the target killed with SIGKILL (usually by the OOM
+    /// killer) as oom-*.out
+    #[arg(long)]
+    pub report_oom: bool,
+
+    /// Limit the address space of the target to this many megabytes
+    #[arg(long, value_name = "MB")]
+    pub mem_limit: Option<u64>,
+
+    /// Limit the number of files that the target can have open, which also
+    /// catches targets that leak file descriptors
+    #[arg(long, value_name = "N")]
+    pub rlimit_nofile: Option<u64>,
+
+    /// Run the target at this nice level, from -20 (favorable) to 19, so that
+    /// fuzzing gets out of the way of other work
+    #[arg(long, value_name = "N", allow_negative_numbers = true,
+          value_parser = clap::value_parser!(i32).range(-20..=19))]
+    pub nice: Option<i32>,
+
+    /// Command to run the interestingness check under, split on whitespace,
+    /// e.g., 'valgrind --error-exitcode=99'
+    #[arg(long, value_name = "CMD")]
+    pub wrapper: Option<String>,
+
+    /// Environment variable to set for the interestingness check
+    #[arg(long, value_name = "KEY=VALUE", value_parser = parse_env)]
+    pub env: Vec<(String, String)>,
+
+    /// Directory for the files that replace '@@'; a tmpfs makes fuzzing
+    /// targets that read files faster [default: the system's]
+    #[arg(long, value_name = "DIR")]
+    pub tmp_dir: Option<PathBuf>,
+
+    /// Working directory for the interestingness check
+    #[arg(long, value_name = "DIR", visible_alias = "check-dir")]
+    pub target_cwd: Option<PathBuf>,
+
     #[clap(flatten)]
     verbose: Verbosity<InfoLevel>,
 
-    /// Input files
+    /// Also append log messages to this file
+    #[arg(long, value_name = "PATH")]
+    pub log_file: Option<PathBuf>,
+
+    /// How many levels of subdirectories of DIR to load files from
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Only load corpus files with these extensions
+    #[arg(long, value_delimiter = ',', value_name = "EXT")]
+    pub extensions: Vec<String>,
+
+    /// Skip corpus files with the same contents as one already loaded
+    #[arg(long)]
+    pub dedup_corpus: bool,
+
+    /// Warn at the end of the run about corpus files that rarely got mutated
+    #[arg(long)]
+    pub warn_on_unused_corpus_file: bool,
+
+    /// Files used fewer than this many times over the run count as unused
+    #[arg(long, default_value_t = 1, requires = "warn_on_unused_corpus_file")]
+    pub unused_threshold: usize,
+
+    /// Input files, or `-` to read a list of them from stdin, one per line
     #[arg(value_name = "DIR", required = true)]
-    pub files: String,
+    pub files: Option<String>,
 
-    /// Interestingness check; fed test case on stdin or via '@@' file
-    #[arg(value_name = "CMD", required = true, num_args = 1..)]
+    /// Interestingness check; fed test case on stdin, or in a file whose path
+    /// replaces every '@@' in the arguments ('@@dir' is replaced by a directory
+    /// holding only that file)
+    #[arg(
+        value_name = "CMD",
+        required_unless_present_any = ["print_mutant", "dry_run"],
+        num_args = 1..
+    )]
     pub check: Vec<String>,
 }
 
-fn read_file(file: &PathBuf) -> Result<String> {
-    fs::read_to_string(file).with_context(|| format!("Failed to read file {}", file.display()))
+/// Modes other than fuzzing
+#[derive(Clone, Debug, clap::Subcommand)]
+pub enum Command {
+    /// Copy the smallest set of corpus files that covers the same node types
+    MinimizeCorpus {
+        /// Input files, or `-` to read a list of them from stdin
+        #[arg(long, value_name = "DIR")]
+        files: PathBuf,
+
+        /// Directory to copy the remaining files to
+        #[arg(long, value_name = "DIR")]
+        output: PathBuf,
+    },
+    /// Run the check once on a saved input; exit 0 if it's still interesting
+    Repro {
+        /// Input to run the check on
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Interestingness check, as for fuzzing
+        #[arg(value_name = "CMD", required = true, num_args = 1..)]
+        check: Vec<String>,
+    },
+    /// Reduce a saved input, as is done for crashes found while fuzzing
+    Reduce {
+        /// Input to reduce
+        #[arg(value_name = "FILE")]
+        file: PathBuf,
+
+        /// Where to write the reduced input
+        #[arg(short, long, value_name = "FILE")]
+        output: PathBuf,
+
+        /// Interestingness check, as for fuzzing
+        #[arg(value_name = "CMD", required = true, num_args = 1..)]
+        check: Vec<String>,
+    },
+}
+
+/// How to name crash and hang files
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum NameScheme {
+    /// A random UUID
+    Uuid,
+    /// A counter; may overwrite files from earlier runs in the same directory
+    Sequential,
+    /// The crash signature (see --dedup), so that repeats get the same name
+    Hash,
+}
+
+/// Where to save crashes
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Layout {
+    /// All in one directory (per thread, unless --flat)
+    Flat,
+    /// In `SIGNAL-OR-EXIT-CODE/SIGNATURE` subdirectories of the output
+    /// directory, so that similar crashes end up together
+    Bucketed,
+}
+
+/// The order of the corpus files that seeds are picked from
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum CorpusOrder {
+    /// Whatever order the files are stored in, which differs between runs
+    Hash,
+    /// By path, so that the same --seed picks the same seeds
+    Sorted,
+    /// Shuffled according to --seed, the same way in every thread
+    Shuffled,
+}
+
+/// Which inputs to save for each crash
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Keep {
+    /// The input as found and the reduced input
+    All,
+    /// Only the reduced input, or the input as found if reduction fails
+    Reduced,
+    /// Only the input as found, without reducing it
+    Raw,
 }
 
-fn parse(language: Language, code: &str) -> Result<Tree> {
+/// How to print statistics while fuzzing
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum StatsFormat {
+    /// Throughput, for people
+    Human,
+    /// One JSON object per line, for programs
+    Json,
+}
+
+/// What makes two crashes count as duplicates
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Dedup {
+    /// Save every crash
+    Off,
+    /// Same signal (or interesting exit) and stderr, ignoring addresses and PIDs
+    Stderr,
+    /// Same signal (or interesting exit)
+    Signal,
+}
+
+fn parse_env(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("expected KEY=VALUE, found {s:?}")),
+    }
+}
+
+fn parse_replacement(s: &str) -> Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((kind, text)) if !kind.is_empty() => Ok((kind.to_string(), text.to_string())),
+        _ => Err(format!("expected KIND=TEXT, found {s:?}")),
+    }
+}
+
+fn read_file(file: &PathBuf) -> Result<Vec<u8>> {
+    fs::read(file).with_context(|| format!("Failed to read file {}", file.display()))
+}
+
+/// Set on Ctrl-C, tells the fuzzing threads to wrap up
+static STOP: OnceLock<Arc<AtomicBool>> = OnceLock::new();
+
+extern "C" fn on_sigint(_: libc::c_int) {
+    if let Some(stop) = STOP.get() {
+        stop.store(true, Ordering::Relaxed);
+    }
+    // A second Ctrl-C kills the process as usual
+    // SAFETY: signal is async-signal-safe.
+    unsafe { libc::signal(libc::SIGINT, libc::SIG_DFL) };
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    // SAFETY: The pointer and length describe a valid, writable buffer.
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if ret != 0 {
+        return String::from("localhost");
+    }
+    let len = buf.iter().position(|b| *b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+/// The CPUs that this process may run on, in order
+#[cfg(target_os = "linux")]
+fn allowed_cpus() -> std::io::Result<Vec<usize>> {
+    // SAFETY: cpu_set_t is plain data, all zeros is the empty set.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    // SAFETY: The pointer and size describe a valid, writable cpu_set_t.
+    let ret = unsafe { libc::sched_getaffinity(0, size_of_val(&set), &mut set) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    let max = usize::try_from(libc::CPU_SETSIZE).unwrap_or_default();
+    // SAFETY: Every index is below CPU_SETSIZE.
+    Ok((0..max)
+        .filter(|cpu| unsafe { libc::CPU_ISSET(*cpu, &set) })
+        .collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allowed_cpus() -> std::io::Result<Vec<usize>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "CPU affinity is only supported on Linux",
+    ))
+}
+
+/// Pin the calling thread to `cpu`
+#[cfg(target_os = "linux")]
+fn pin_to_cpu(cpu: usize) -> std::io::Result<()> {
+    // SAFETY: cpu_set_t is plain data, all zeros is the empty set.
+    let mut set: libc::cpu_set_t = unsafe { std::mem::zeroed() };
+    // SAFETY: `cpu` came from allowed_cpus, so it's below CPU_SETSIZE.
+    unsafe { libc::CPU_SET(cpu, &mut set) };
+    // SAFETY: The pointer and size describe a valid cpu_set_t.
+    let ret = unsafe { libc::sched_setaffinity(0, size_of_val(&set), &set) };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn pin_to_cpu(_cpu: usize) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Parse raw bytes, which need not be UTF-8, so that node ranges line up with
+/// the input
+fn parse(language: Language, code: &[u8]) -> Result<Tree> {
     let mut parser = tree_sitter::Parser::new();
     parser
         .set_language(language)
@@ -124,74 +744,152 @@ fn parse(language: Language, code: &str) -> Result<Tree> {
     parser.parse(code, None).context("Failed to parse code")
 }
 
+/// All nodes of `tree`, in pre-order
+fn nodes(tree: &Tree) -> Vec<tree_sitter::Node<'_>> {
+    let mut nodes = Vec::new();
+    let mut cursor = tree.walk();
+    'walk: loop {
+        nodes.push(cursor.node());
+        if cursor.goto_first_child() || cursor.goto_next_sibling() {
+            continue;
+        }
+        while cursor.goto_parent() {
+            if cursor.goto_next_sibling() {
+                continue 'walk;
+            }
+        }
+        return nodes;
+    }
+}
+
+/// A regex matching `base` or any of `also`
+fn alternatives(base: &str, also: &[String]) -> String {
+    if also.is_empty() {
+        return base.to_string();
+    }
+    std::iter::once(base)
+        .chain(also.iter().map(String::as_str))
+        .map(|r| format!("(?:{r})"))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+/// Output that makes a test interesting when --interesting-stdout or
+/// --interesting-stderr isn't given
+pub const DEFAULT_INTERESTING_PATTERNS: &[&str] =
+    &[r"AddressSanitizer", r"Fatal", r"DCHECK", r"Check"];
+
+/// Output that makes a test boring when --uninteresting-stdout or
+/// --uninteresting-stderr isn't given
+pub const DEFAULT_UNINTERESTING_PATTERNS: &[&str] = &[
+    r"RangeError",
+    r"SyntaxError",
+    r"ReferenceError",
+    r"TypeError",
+    r"URIError",
+    r"EvalError",
+    r"InternalError",
+];
+
 #[allow(clippy::too_many_arguments)]
 fn make_check(
     debug: bool,
     timeout: Duration,
     check: Vec<String>,
     mut interesting_exit_codes: Vec<i32>,
+    any_nonzero_exit: bool,
+    default_signal_codes: bool,
+    ignored_signals: Vec<i32>,
     interesting_stdout: Option<String>,
     interesting_stderr: Option<String>,
     uninteresting_stdout: Option<String>,
     uninteresting_stderr: Option<String>,
+    interesting_output: Option<String>,
+    uninteresting_output: Option<String>,
+    also_interesting_stderr: &[String],
+    also_uninteresting_stderr: &[String],
+    case_insensitive: bool,
+    multi_line: bool,
+    target_cwd: Option<PathBuf>,
+    env: Vec<(String, String)>,
+    tmp_dir: Option<PathBuf>,
+    interesting_output_bytes: Option<usize>,
+    mem_limit: Option<u64>,
 ) -> Result<CmdCheck> {
     if check.is_empty() {
-        eprintln!("Internal error: empty interestingness check!");
-        std::process::exit(1);
+        anyhow::bail!("Empty interestingness check");
     }
     let mut argv: Vec<_> = check.iter().collect();
     let cmd = argv[0];
     argv.remove(0);
 
-    let default_interesting_patterns = vec![r"AddressSanitizer", r"Fatal", r"DCHECK", r"Check"];
-
-    let default_uninteresting_patterns = vec![
-        r"RangeError",
-        r"SyntaxError",
-        r"ReferenceError",
-        r"TypeError",
-        r"URIError",
-        r"EvalError",
-        r"InternalError",
-    ];
+    let new_regex = |r: &str| {
+        RegexBuilder::new(r)
+            .case_insensitive(case_insensitive)
+            .multi_line(multi_line)
+            .build()
+    };
 
     let stdout_regex = match &interesting_stdout {
-        Some(r) => Some(Regex::new(r).context("Invalid interesting stdout regex")?),
+        Some(r) => Some(new_regex(r).context("Invalid interesting stdout regex")?),
         None => Some(
-            Regex::new(&default_interesting_patterns.join("|"))
+            new_regex(&DEFAULT_INTERESTING_PATTERNS.join("|"))
                 .context("Invalid default interesting stdout regex")?,
         ),
     };
 
     let stderr_regex = match &interesting_stderr {
-        Some(r) => Some(Regex::new(r).context("Invalid interesting stderr regex")?),
+        Some(r) => Some(
+            new_regex(&alternatives(r, also_interesting_stderr))
+                .context("Invalid interesting stderr regex")?,
+        ),
         None => Some(
-            Regex::new(&default_interesting_patterns.join("|"))
-                .context("Invalid default interesting stderr regex")?,
+            new_regex(&alternatives(
+                &DEFAULT_INTERESTING_PATTERNS.join("|"),
+                also_interesting_stderr,
+            ))
+            .context("Invalid default interesting stderr regex")?,
         ),
     };
 
     let un_stdout_regex = match &uninteresting_stdout {
-        Some(r) => Some(Regex::new(r).context("Invalid uninteresting stdout regex")?),
+        Some(r) => Some(new_regex(r).context("Invalid uninteresting stdout regex")?),
         None => Some(
-            Regex::new(&default_uninteresting_patterns.join("|"))
+            new_regex(&DEFAULT_UNINTERESTING_PATTERNS.join("|"))
                 .context("Invalid default uninteresting stdout regex")?,
         ),
     };
 
     let un_stderr_regex = match &uninteresting_stderr {
-        Some(r) => Some(Regex::new(r).context("Invalid uninteresting stderr regex")?),
+        Some(r) => Some(
+            new_regex(&alternatives(r, also_uninteresting_stderr))
+                .context("Invalid uninteresting stderr regex")?,
+        ),
         None => Some(
-            Regex::new(&default_uninteresting_patterns.join("|"))
-                .context("Invalid default uninteresting stderr regex")?,
+            new_regex(&alternatives(
+                &DEFAULT_UNINTERESTING_PATTERNS.join("|"),
+                also_uninteresting_stderr,
+            ))
+            .context("Invalid default uninteresting stderr regex")?,
         ),
     };
-    interesting_exit_codes.extend(128..256);
+    let output_regex = interesting_output
+        .map(|r| new_regex(&r).context("Invalid interesting output regex"))
+        .transpose()?;
+    let un_output_regex = uninteresting_output
+        .map(|r| new_regex(&r).context("Invalid uninteresting output regex"))
+        .transpose()?;
+    if any_nonzero_exit {
+        interesting_exit_codes.extend(1..256);
+    } else if default_signal_codes {
+        // Shells report a child killed by a signal this way
+        interesting_exit_codes.extend((128..256).filter(|c| !ignored_signals.contains(&(c - 128))));
+    }
     Ok(CmdCheck::new(
         cmd.to_string(),
         argv.iter().map(|s| s.to_string()).collect(),
         interesting_exit_codes,
-        None,
+        tmp_dir,
         stdout_regex,
         stderr_regex,
         un_stdout_regex,
@@ -199,205 +897,2180 @@ fn make_check(
         debug,
         debug,
         Some(timeout),
-    ))
+    )
+    .with_cwd(target_cwd)
+    .with_ignored_signals(ignored_signals)
+    .with_mem_limit(mem_limit)
+    .with_env(env)
+    .with_output_regexes(output_regex, un_output_regex)
+    .with_interesting_output_bytes(interesting_output_bytes))
 }
 
-const BATCH: usize = 100_000; // not all materialized at once
+// Used by --print-mutant when the mutation options aren't given
+const DEFAULT_CHAOS: u8 = 5;
+const DEFAULT_DELETIONS: u8 = 5;
+const DEFAULT_MUTATIONS: usize = 16;
+
+/// What [`check`] made of one test
+#[derive(Debug)]
+enum CheckOutcome {
+    /// The target crashed or otherwise passed the check, whether or not the
+    /// crash was new
+    Interesting,
+    Boring,
+    /// The target timed out
+    Hang,
+    /// The target was killed for using too much memory
+    Oom,
+    /// The target couldn't be run, or its output couldn't be collected
+    StartError(String),
+}
 
+#[allow(clippy::too_many_arguments)]
 fn check(
     language: Language,
     node_types: &treereduce::NodeTypes,
+    args: &Args,
     chk: &CmdCheck,
+    server: Option<&mut (dyn persistent::Server + '_)>,
+    origin: &str,
+    out_dir: &Path,
+    signatures: &Mutex<Signatures>,
+    progress: &Progress,
+    stats: &mut JobStats,
+    source: Source<'_>,
     inp: &[u8],
-) -> i32 {
-    let state = match chk.start(inp) {
-        Ok(s) => s,
-        Err(e) => {
-            eprintln!("Problem when running target: {e}");
-            return -1;
+) -> CheckOutcome {
+    let persistent = server.is_some();
+    let (status, output) = if let Some(server) = server {
+        let quick = match server.run(inp) {
+            Ok(o) => o,
+            Err(e) => return CheckOutcome::StartError(e.to_string()),
+        };
+        stats.execs += 1;
+        progress.execs.fetch_add(1, Ordering::Relaxed);
+        progress.mutant_sizes.record(inp.len());
+        match quick {
+            Outcome::Boring => return CheckOutcome::Boring,
+            Outcome::Timeout if !args.report_hangs => return CheckOutcome::Hang,
+            _ => (),
+        }
+        // Confirm with a fresh process, which also gets us its output
+        match chk.run(inp) {
+            Ok(r) => r,
+            Err(e) => return CheckOutcome::StartError(e.to_string()),
+        }
+    } else {
+        let state = match chk.exec(inp) {
+            Ok(s) => s,
+            Err(e) => return CheckOutcome::StartError(e.to_string()),
+        };
+        let r = chk.wait_with_output(state);
+        stats.execs += 1;
+        progress.execs.fetch_add(1, Ordering::Relaxed);
+        progress.mutant_sizes.record(inp.len());
+        match r {
+            Ok(r) => r,
+            Err(e) => return CheckOutcome::StartError(e.to_string()),
         }
     };
-    let (interesting, status, stdout, stderr) = chk.wait_with_output(state).unwrap();
-    let code = status.and_then(|s| s.code()).unwrap_or(-1);
-    let sig = status.and_then(|s| s.signal());
-    if interesting || sig.is_some() {
-        if let Some(s) = sig {
-            if s == 6 {
-                return code;
+    let outcome = chk.classify(status, &output);
+    if persistent && outcome == Outcome::Boring {
+        warn!("Input was only interesting to a long-running target");
+    }
+    match outcome {
+        Outcome::Timeout if args.report_hangs => {
+            info!("hang!");
+            stats.hangs += 1;
+            progress.hangs.fetch_add(1, Ordering::Relaxed);
+            let stem = artifact_stem(args, "hang", origin, progress, || {
+                signature(args.dedup, outcome, &output.stderr)
+            });
+            let saved = save_artifacts(out_dir, &stem, Some(inp), &output).and_then(|()| {
+                Metadata::new(args, chk, status, source)
+                    .with_sizes(inp.len(), None)
+                    .save(out_dir, &stem)
+            });
+            if let Err(e) = saved {
+                warn!("Failed to save hang: {e:#}");
             }
-            eprintln!("signal {s}!");
-        } else {
-            eprintln!("interesting!");
-        }
-        // let mut rng = rand::thread_rng();
-        let i = Uuid::new_v4(); //rng.gen_range(0..10192);
-        fs::write(format!("crash-{i}.out"), inp).unwrap();
-        fs::write(format!("crash-{i}.stdout"), stdout).unwrap();
-        fs::write(format!("crash-{i}.stderr"), stderr).unwrap();
-        let tree = parse(language, &String::from_utf8_lossy(inp)).unwrap();
-        match treereduce::treereduce_multi_pass(
-            language,
-            node_types,
-            treereduce::Original::new(tree, inp.to_vec()),
-            &treereduce::Config {
-                check: chk.clone(),
-                delete_non_optional: true,
-                jobs: 1,
-                min_reduction: 2,
-                replacements: HashMap::new(),
-            },
-            Some(8),
-        ) {
-            Err(e) => eprintln!("Failed to reduce! {e}"),
-            Ok((reduced, _)) => {
-                fs::write(format!("crash-{i}.reduced.out"), reduced.text).unwrap();
+            return CheckOutcome::Hang;
+        }
+        Outcome::Oom => {
+            stats.ooms += 1;
+            progress.ooms.fetch_add(1, Ordering::Relaxed);
+            if args.report_oom {
+                info!("oom!");
+                let stem = artifact_stem(args, "oom", origin, progress, || {
+                    signature(args.dedup, outcome, &output.stderr)
+                });
+                let saved = save_artifacts(out_dir, &stem, Some(inp), &output).and_then(|()| {
+                    Metadata::new(args, chk, status, source)
+                        .with_sizes(inp.len(), None)
+                        .save(out_dir, &stem)
+                });
+                if let Err(e) = saved {
+                    warn!("Failed to save oom: {e:#}");
+                }
             }
+            return CheckOutcome::Oom;
         }
+        Outcome::Timeout => return CheckOutcome::Hang,
+        Outcome::Boring => return CheckOutcome::Boring,
+        Outcome::Signal(s) => info!("signal {s}!"),
+        Outcome::Interesting => info!("interesting!"),
     }
-    code
-}
-
-// TODO: print executions/sec
-fn job(
-    language: Language,
-    // HACK: there should be another crate that deals with this...
-    node_types1: &treereduce::NodeTypes,
-    node_types2: &tree_splicer::node_types::NodeTypes,
-    args: &Args,
-    files: &HashMap<String, (Vec<u8>, Tree)>,
-    chk: CmdCheck,
-) {
-    if files.is_empty() {
-        eprintln!("No files provided.");
-        return;
+    let dedup = args.dedup != Dedup::Off;
+    let duplicate = |sig| !signatures.lock().unwrap().insert(sig);
+    // Known crashes aren't worth reducing, unless it's the reduced input that
+    // decides what counts as known
+    let early = (dedup && !args.normalize_crashes_before_dedup)
+        .then(|| signature(args.dedup, outcome, &output.stderr));
+    if early.is_some_and(duplicate) {
+        stats.duplicates += 1;
+        return CheckOutcome::Interesting;
     }
-    #[cfg(feature = "radamsa")]
-    if args.radamsa {
-        unsafe { radamsa_sys::radamsa_init() };
-        let mut rng = rand::thread_rng();
-        let file_bytes: Vec<_> = files.values().map(|(bytes, _tree)| bytes).collect();
-        loop {
-            const MAX_SIZE: usize = 4096;
-            // TODO: Mutate in-place
-            let mut input: Vec<u8> = file_bytes
-                .get(rng.gen_range(0..files.len()))
-                .unwrap()
-                .to_vec();
-            let mut mutant = vec![0u8; MAX_SIZE];
-            let out_len = unsafe {
-                radamsa_sys::radamsa(
-                    input.as_mut_ptr(),
-                    input.len(),
-                    mutant.as_mut_ptr(),
-                    MAX_SIZE,
-                    0,
-                )
-            };
-            assert!(out_len <= MAX_SIZE);
-            mutant.truncate(out_len);
-            check(language, node_types1, &chk, &mutant);
+    let reduced = if args.no_reduce || args.reduce_passes == Some(0) || args.keep == Keep::Raw {
+        None
+    } else {
+        match parse(language, inp)
+            .map_err(|e| warn!("Failed to parse crashing input: {e:#}"))
+            .ok()
+            .and_then(|tree| reduce(language, node_types, chk, &tree, inp, args))
+        {
+            None => {
+                warn!("Failed to reduce!");
+                None
+            }
+            Some((reduced, attempt)) => {
+                info!(
+                    "Reduced from {} to {} bytes ({:.1}% smaller) with {} settings",
+                    inp.len(),
+                    reduced.len(),
+                    percent_smaller(inp.len(), reduced.len()),
+                    REDUCE_ATTEMPTS[attempt].name
+                );
+                Some((reduced, attempt))
+            }
         }
+    };
+    let (reduced, attempt) = reduced.unzip();
+    let rerun = reduced
+        .as_ref()
+        .map(|r| confirm(&reduce_check(chk, args), r));
+    let reproduced = rerun.as_ref().map(|(_, crashed)| *crashed);
+    if reproduced == Some(false) {
+        warn!("Reduced input doesn't crash, saving it as unconfirmed");
     }
-    loop {
-        let mut rng: rand::prelude::ThreadRng = rand::thread_rng();
-        const INTER_SPLICES_RANGE: std::ops::Range<usize> = 12..48;
-        const CHAOS_RANGE: std::ops::Range<u8> = 15..20;
-        const DELETIONS_RANGE: std::ops::Range<u8> = 10..20;
-
-        let random_inter_splices = rng.gen_range(INTER_SPLICES_RANGE);
-        let random_seed = rng.gen::<u64>();
-        let random_chaos_range = rng.gen_range(CHAOS_RANGE);
-        let random_deletions_range = rng.gen_range(DELETIONS_RANGE);
-
-        let config = Config {
-            chaos: random_chaos_range,         //args.chaos,
-            deletions: random_deletions_range, //args.deletions,
-            language,
-            // intra_splices: 10,
-            inter_splices: random_inter_splices, //args.mutations,
-            node_types: node_types2.clone(),
-            max_size: args.max_size,
-            reparse: usize::MAX,
-            seed: random_seed, //args.seed,
-        };
-        let start = Instant::now();
-        let mut execs = 0;
-        for (i, out) in Splicer::new(config, files).enumerate() {
-            if i == BATCH {
-                break;
-            }
-            let _code = check(language, node_types1, &chk, &out);
-            execs += 1;
-            let secs = start.elapsed().as_secs();
-            if execs % 10_00 == 0 {
-                println!("execs/sec: {}", execs / secs);
+    let sig = match early {
+        Some(sig) => Some(sig),
+        None if !dedup => None,
+        None => {
+            // Reduced inputs tend to produce more canonical output
+            let sig_stderr = match &rerun {
+                Some((Ok((_status, rerun_output)), true)) => &rerun_output.stderr,
+                _ => &output.stderr,
+            };
+            let sig = signature(args.dedup, outcome, sig_stderr);
+            if duplicate(sig) {
+                stats.duplicates += 1;
+                return CheckOutcome::Interesting;
             }
+            Some(sig)
         }
+    };
+    stats.crashes += 1;
+    progress.crashes.fetch_add(1, Ordering::Relaxed);
+    progress.crash_sizes.record(inp.len());
+    if let Some(r) = &reduced {
+        stats.reduced += 1;
+        stats.reduced_from += inp.len();
+        stats.reduced_to += r.len();
     }
+    let counts = match (outcome, status.and_then(|s| s.code())) {
+        (Outcome::Signal(s), _) => Some((&progress.crash_signals, s)),
+        (_, Some(code)) => Some((&progress.crash_exit_codes, code)),
+        (_, None) => None,
+    };
+    if let Some((counts, key)) = counts {
+        *counts.lock().unwrap().entry(key).or_default() += 1;
+    }
+    // Only needed for naming when not deduplicating
+    let name_sig = || sig.unwrap_or_else(|| signature(args.dedup, outcome, &output.stderr));
+    let out_dir = match args.layout {
+        Layout::Flat => Cow::Borrowed(out_dir),
+        Layout::Bucketed => Cow::Owned(args.output.join(bucket(outcome, status, name_sig()))),
+    };
+    let stem = artifact_stem(args, "crash", origin, progress, name_sig);
+    let raw = keep_raw(args.keep, reproduced).then_some(inp);
+    let input = if raw.is_some() {
+        format!("{stem}.out")
+    } else {
+        format!("{stem}.reduced.out")
+    };
+    let saved = fs::create_dir_all(&out_dir)
+        .with_context(|| format!("Failed to create directory {}", out_dir.display()))
+        .and_then(|()| save_artifacts(&out_dir, &stem, raw, &output))
+        .and_then(|()| {
+            Metadata::new(args, chk, status, source)
+                .with_signature(sig)
+                .with_sizes(inp.len(), reduced.as_ref().map(Vec::len))
+                .with_reduce_attempt(attempt)
+                .with_reduced_reproduces(reproduced)
+                .save(&out_dir, &stem)
+        })
+        .and_then(|()| {
+            if args.emit_repro {
+                save_script(&out_dir, &stem, &input, chk)
+            } else {
+                Ok(())
+            }
+        })
+        .and_then(|()| {
+            let Some(reduced) = &reduced else {
+                return Ok(());
+            };
+            let ext = if reproduced == Some(true) {
+                "reduced.out"
+            } else {
+                "reduced.unconfirmed.out"
+            };
+            let path = out_dir.join(format!("{stem}.{ext}"));
+            fs::write(&path, reduced).with_context(|| format!("Failed to write {}", path.display()))
+        });
+    if let Err(e) = saved {
+        // Not worth stopping the campaign for, e.g., a full disk
+        warn!("Failed to save crash: {e:#}");
+        return CheckOutcome::Interesting;
+    }
+    if let Some(cmd) = &args.on_crash {
+        let signal = match outcome {
+            Outcome::Signal(s) => Some(s),
+            _ => None,
+        };
+        on_crash(cmd, &out_dir.join(&input), signal);
+    }
+    CheckOutcome::Interesting
 }
 
-// TODO: graceful exit
-pub fn main(language: Language, node_types_json_str: &'static str) -> Result<()> {
-    let args = Args::parse();
-    debug_assert!(args.interesting_stdout.is_some() || args.uninteresting_stdout.is_none());
-    debug_assert!(args.interesting_stderr.is_some() || args.uninteresting_stderr.is_none());
+/// Whether to save a crashing input as found, given whether its reduction
+/// crashed when run again
+fn keep_raw(keep: Keep, reproduced: Option<bool>) -> bool {
+    // Nothing is lost if reduction failed
+    keep != Keep::Reduced || reproduced != Some(true)
+}
 
-    if args.debug {
-        eprintln!("Loading testcases...");
+/// Start the --on-crash hook for a saved input, without waiting for it
+fn on_crash(cmd: &str, path: &Path, signal: Option<i32>) {
+    let signal = signal.map(|s| s.to_string()).unwrap_or_default();
+    let child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .arg("sh")
+        .arg(path)
+        .arg(&signal)
+        .env("TREE_CRASHER_CRASH", path)
+        .env("TREE_CRASHER_SIGNAL", &signal)
+        .stdin(Stdio::null())
+        .spawn();
+    let mut child = match child {
+        Ok(c) => c,
+        Err(e) => {
+            warn!("Failed to run --on-crash hook: {e}");
+            return;
+        }
+    };
+    let path = path.to_path_buf();
+    // Reap it in the background so that fuzzing carries on
+    std::thread::spawn(move || match child.wait() {
+        Ok(status) if status.success() => (),
+        Ok(status) => warn!(
+            "--on-crash hook for {} exited with {status}",
+            path.display()
+        ),
+        Err(e) => warn!("Failed to wait for --on-crash hook: {e}"),
+    });
+}
+
+/// Run the target on a file and describe how the check judged it
+fn explain(chk: &CmdCheck, path: &Path) -> Result<()> {
+    let inp = fs::read(path).with_context(|| format!("Failed to read file {}", path.display()))?;
+    let (status, output) = chk.run(&inp).context("Failed to run target")?;
+    let outcome = chk.classify(status, &output);
+    let Some(status) = status else {
+        println!("Timed out");
+        println!("Verdict: {outcome:?}");
+        return Ok(());
+    };
+    let show = |n: Option<i32>| n.map_or_else(|| String::from("none"), |n| n.to_string());
+    println!("Exit code: {}", show(status.code()));
+    println!("Signal: {}", show(status.signal()));
+    let matches = chk.matches(&status, &output);
+    println!("Interesting exit code: {}", matches.exit_code);
+    if chk.is_persistent() {
+        println!("Interesting reply: {}", matches.reply);
     }
-    let mut files = HashMap::new();
-    // TODO error messages
-    for entry in fs::read_dir(&args.files)
-        .with_context(|| format!("When reading tests from {}", args.files))?
-    {
-        let entry = entry?;
-        let path = entry.path();
-        if let Ok(s) = read_file(&path) {
-            let tree = parse(language, &s)?;
-            files.insert(String::from(path.to_string_lossy()), (s.into_bytes(), tree));
+    println!("Interesting output size: {}", matches.output_bytes);
+    for (name, m) in [
+        ("Interesting stdout", &matches.interesting_stdout),
+        ("Interesting stderr", &matches.interesting_stderr),
+        ("Uninteresting stdout", &matches.uninteresting_stdout),
+        ("Uninteresting stderr", &matches.uninteresting_stderr),
+        ("Interesting output", &matches.interesting_output),
+        ("Uninteresting output", &matches.uninteresting_output),
+    ] {
+        match m {
+            Some(m) => println!("{name}: /{}/ matched {:?}", m.pattern, m.text),
+            None => println!("{name}: no match"),
         }
     }
-    let chk = make_check(
-        args.debug,
-        Duration::from_millis(args.timeout),
-        args.check.clone(),
-        args.interesting_exit_code.clone(),
-        args.interesting_stdout.clone(),
-        args.interesting_stderr.clone(),
+    println!("Verdict: {outcome:?}");
+    Ok(())
+}
+
+/// Run the target on a saved input, returning whether it was interesting
+fn repro(args: &Args, chk: &CmdCheck, path: &Path) -> Result<bool> {
+    let inp = fs::read(path).with_context(|| format!("Failed to read file {}", path.display()))?;
+    let (status, output) = chk.run(&inp).context("Failed to run target")?;
+    let reproduced = match chk.classify(status, &output) {
+        Outcome::Interesting | Outcome::Signal(_) => true,
+        Outcome::Timeout => args.report_hangs,
+        Outcome::Oom => args.report_oom,
+        Outcome::Boring => false,
+    };
+    let show = |n: Option<i32>| n.map_or_else(|| String::from("none"), |n| n.to_string());
+    println!("Reproduced: {reproduced}");
+    match status {
+        None => println!("Timed out"),
+        Some(status) => {
+            println!("Exit code: {}", show(status.code()));
+            println!("Signal: {}", show(status.signal()));
+        }
+    }
+    let mut out = std::io::stdout();
+    for (name, bytes) in [("stdout", &output.stdout), ("stderr", &output.stderr)] {
+        writeln!(out, "--- {name} ---")
+            .and_then(|()| out.write_all(bytes))
+            .context("Failed to print output")?;
+    }
+    Ok(reproduced)
+}
+
+/// Write an executable script that runs the target on a saved input
+fn save_script(dir: &Path, stem: &str, input: &str, chk: &CmdCheck) -> Result<()> {
+    let path = dir.join(format!("{stem}.sh"));
+    fs::write(&path, chk.script(input))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to make {} executable", path.display()))
+}
+
+/// Filename (without extension) for a new artifact
+fn artifact_stem(
+    args: &Args,
+    kind: &str,
+    origin: &str,
+    progress: &Progress,
+    sig: impl FnOnce() -> u64,
+) -> String {
+    match args.name_scheme {
+        NameScheme::Uuid => format!("{kind}-{origin}-{}", Uuid::new_v4()),
+        NameScheme::Sequential => {
+            let n = progress.artifacts.fetch_add(1, Ordering::Relaxed) + 1;
+            format!("{kind}-{origin}-{n:06}")
+        }
+        NameScheme::Hash => format!("{kind}-{origin}-{:016x}", sig()),
+    }
+}
+
+/// Subdirectory for a crash with --layout bucketed
+fn bucket(outcome: Outcome, status: Option<ExitStatus>, sig: u64) -> PathBuf {
+    let kind = match (outcome, status.and_then(|s| s.code())) {
+        (Outcome::Signal(s), _) => format!("signal-{s}"),
+        (_, Some(code)) => format!("exit-{code}"),
+        // Interesting because of its output, but there's no exit code
+        (_, None) => String::from("other"),
+    };
+    Path::new(&kind).join(format!("{sig:016x}"))
+}
+
+/// Write an interesting input and the target's output on it
+///
+/// The input is skipped if `None`, e.g., if only the reduced input is kept.
+fn save_artifacts(dir: &Path, stem: &str, inp: Option<&[u8]>, output: &Output) -> Result<()> {
+    for (ext, bytes) in [
+        ("out", inp),
+        ("stdout", Some(output.stdout.as_slice())),
+        ("stderr", Some(output.stderr.as_slice())),
+    ] {
+        let Some(bytes) = bytes else {
+            continue;
+        };
+        let path = dir.join(format!("{stem}.{ext}"));
+        fs::write(&path, bytes).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Settings for one attempt at reducing an interesting input
+#[derive(Debug)]
+struct ReduceAttempt {
+    name: &'static str,
+    delete_non_optional: bool,
+    passes: usize,
+}
+
+/// Tried in order until one produces a strictly smaller input
+const REDUCE_ATTEMPTS: [ReduceAttempt; 2] = [
+    ReduceAttempt {
+        name: "aggressive",
+        delete_non_optional: true,
+        passes: 8,
+    },
+    // Aggressive reduction sometimes errors out or gets stuck on grammars
+    // where deleting non-optional nodes produces unparseable programs.
+    ReduceAttempt {
+        name: "conservative",
+        delete_non_optional: false,
+        passes: 2,
+    },
+];
+
+/// The --reduce-replace pairs grouped by node kind, as treereduce wants them
+///
+/// treereduce wants them to live forever, so they're leaked, but only once
+/// for each set of pairs.
+fn replacements(pairs: &[(String, String)]) -> HashMap<&'static str, &'static [&'static str]> {
+    type Replacements = HashMap<&'static str, &'static [&'static str]>;
+    type Pairs = Vec<(String, String)>;
+    static LEAKED: Mutex<Vec<(Pairs, Replacements)>> = Mutex::new(Vec::new());
+    let mut leaked = LEAKED.lock().unwrap();
+    if let Some((_, map)) = leaked.iter().find(|(p, _)| p == pairs) {
+        return map.clone();
+    }
+    let mut grouped: BTreeMap<&str, Vec<&'static str>> = BTreeMap::new();
+    for (kind, text) in pairs {
+        grouped
+            .entry(kind)
+            .or_default()
+            .push(Box::leak(text.clone().into_boxed_str()));
+    }
+    let map: Replacements = grouped
+        .into_iter()
+        .map(|(kind, texts)| {
+            let kind: &'static str = Box::leak(kind.to_string().into_boxed_str());
+            let texts: &'static [&'static str] = Box::leak(texts.into_boxed_slice());
+            (kind, texts)
+        })
+        .collect();
+    leaked.push((pairs.to_vec(), map.clone()));
+    map
+}
+
+/// The check to reduce with, which has its own timeout
+fn reduce_check(chk: &CmdCheck, args: &Args) -> CmdCheck {
+    match args.reduce_timeout {
+        Some(ms) => chk.clone().with_timeout(Some(Duration::from_millis(ms))),
+        None => chk.clone(),
+    }
+}
+
+/// Run the reduction check on a reduced input again, returning the run and
+/// whether it crashed
+///
+/// Reduction can be led astray by a flaky target, so make sure that the
+/// reduced input still crashes.
+fn confirm(
+    chk: &CmdCheck,
+    reduced: &[u8],
+) -> (std::io::Result<(Option<ExitStatus>, Output)>, bool) {
+    let rerun = chk.run(reduced);
+    let crashed = matches!(&rerun, Ok((status, output)) if chk.is_crash(*status, output));
+    (rerun, crashed)
+}
+
+fn reduce(
+    language: Language,
+    node_types: &treereduce::NodeTypes,
+    chk: &CmdCheck,
+    tree: &Tree,
+    inp: &[u8],
+    args: &Args,
+) -> Option<(Vec<u8>, usize)> {
+    let chk = reduce_check(chk, args);
+    let attempts = REDUCE_ATTEMPTS
+        .iter()
+        .enumerate()
+        .filter(|(_, a)| !(args.reduce_keep_required && a.delete_non_optional));
+    for (i, attempt) in attempts {
+        match treereduce::treereduce_multi_pass(
+            language,
+            node_types,
+            treereduce::Original::new(tree.clone(), inp.to_vec()),
+            &treereduce::Config {
+                check: chk.clone(),
+                delete_non_optional: attempt.delete_non_optional,
+                jobs: args.reduce_jobs,
+                min_reduction: args.reduce_min_step,
+                replacements: replacements(&args.reduce_replace),
+            },
+            Some(args.reduce_passes.unwrap_or(attempt.passes)),
+        ) {
+            Err(e) => warn!("Failed to reduce with {} settings! {e}", attempt.name),
+            Ok((reduced, _)) if reduced.text.len() < inp.len() => return Some((reduced.text, i)),
+            Ok(_) => debug!("No reduction with {} settings", attempt.name),
+        }
+    }
+    None
+}
+
+/// Number of recent tests each thread remembers for --skip-seen
+const SKIP_SEEN_CAPACITY: usize = 1 << 16;
+
+/// Give up if the target couldn't be run this many times before it ever ran
+const MAX_START_ERRORS: usize = 16;
+
+#[allow(clippy::too_many_arguments)]
+fn job(
+    groups: &[Group],
+    args: &Args,
+    worker: usize,
+    weights: &[(glob::Pattern, u32)],
+    dict: &[Vec<u8>],
+    chk: CmdCheck,
+    origin: &str,
+    signatures: &Mutex<Signatures>,
+    progress: &Progress,
+    heartbeat: &AtomicU64,
+) -> JobStats {
+    let mut stats = JobStats::default();
+    let out_dir = worker_dir(args, worker);
+    // Mix in the worker index so that threads don't all make the same choices
+    let mut rng = StdRng::seed_from_u64(
+        args.seed
+            ^ u64::try_from(worker)
+                .unwrap_or_default()
+                .wrapping_mul(0x9E37_79B9_7F4A_7C15),
+    );
+    // Seeds promoted by feedback are local to this thread
+    let mut corpora: Vec<_> = groups.iter().map(|g| Cow::Borrowed(&g.files)).collect();
+    // The seed that each promoted seed was mutated from
+    let mut parents: HashMap<String, String> = HashMap::new();
+    let sizes = WeightedIndex::new(groups.iter().map(|g| g.files.len())).unwrap();
+    let mut seen = if args.skip_seen {
+        Some(Signatures::new(SKIP_SEEN_CAPACITY))
+    } else {
+        None
+    };
+    let mut server: Option<Box<dyn persistent::Server>> = if args.persistent {
+        Some(Box::new(persistent::Persistent::new(
+            &chk,
+            args.persistent_runs,
+        )))
+    } else {
+        None
+    };
+    #[cfg(feature = "forkserver")]
+    if args.forkserver {
+        match forkserver::ForkServer::new(&chk) {
+            Ok(f) => server = Some(Box::new(f)),
+            Err(e) => {
+                error!("Failed to set up forkserver: {e}");
+                stats.start_errors += 1;
+                progress.stop.store(true, Ordering::Relaxed);
+                return stats;
+            }
+        }
+    }
+    let mut time_buckets = if args.time_feedback {
+        Some(TimeBuckets::new())
+    } else {
+        None
+    };
+    #[cfg(feature = "coverage")]
+    let mut coverage = args
+        .coverage_cmd
+        .clone()
+        .map(|cmd| coverage::Coverage::new(cmd, args.target_cwd.clone()));
+    while !progress.should_stop(args) {
+        // Splices only make sense within a language
+        let g = rng.sample(&sizes);
+        let (group, corpus) = (&groups[g], &mut corpora[g]);
+        let mut promoted = Vec::new();
+        let mut uses: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut mutator = mutator::new(
+            group.language,
+            &group.node_types2,
+            args,
+            corpus,
+            weights,
+            dict,
+            &mut rng,
+        );
+        for _ in 0..args.batch {
+            if progress.should_stop(args) {
+                break;
+            }
+            let Some((out, source)) = mutator.next(&mut rng) else {
+                break;
+            };
+            if let Some(seen) = &mut seen {
+                let mut hasher = DefaultHasher::new();
+                out.hash(&mut hasher);
+                if !seen.insert(hasher.finish()) {
+                    stats.skipped += 1;
+                    continue;
+                }
+            }
+            progress.throttle(args);
+            heartbeat.store(progress.uptime_ms(), Ordering::Relaxed);
+            *uses.entry(source.file).or_default() += 1;
+            let ancestors = ancestors(&parents, source.file);
+            let source = Source {
+                ancestors: &ancestors,
+                ..source
+            };
+            let exec_start = Instant::now();
+            let outcome = check(
+                group.language,
+                &group.node_types1,
+                args,
+                &chk,
+                server.as_deref_mut(),
+                origin,
+                &out_dir,
+                signatures,
+                progress,
+                &mut stats,
+                source,
+                &out,
+            );
+            heartbeat.store(progress.uptime_ms(), Ordering::Relaxed);
+            if let CheckOutcome::StartError(e) = outcome {
+                warn!("Problem when running target: {e}");
+                stats.start_errors += 1;
+                if stats.execs == 0 && stats.start_errors >= MAX_START_ERRORS {
+                    // It's never going to work, so don't keep trying
+                    progress.stop.store(true, Ordering::Relaxed);
+                    break;
+                }
+                continue;
+            }
+            let mut promote = server.as_ref().is_some_and(|s| s.new_coverage());
+            if let Some(buckets) = &mut time_buckets {
+                promote |= buckets.record(exec_start.elapsed());
+            }
+            #[cfg(feature = "coverage")]
+            if let Some(coverage) = &mut coverage {
+                match coverage.record() {
+                    Ok(new) => promote |= new,
+                    Err(e) => warn!("Failed to collect coverage: {e}"),
+                }
+            }
+            if promote {
+                promoted.push((out, source.file.to_string()));
+            }
+        }
+        if args.warn_on_unused_corpus_file {
+            for (key, n) in uses {
+                match stats.uses.get_mut(key) {
+                    Some(total) => *total += n,
+                    None => {
+                        stats.uses.insert(key.to_string(), n);
+                    }
+                }
+            }
+        }
+        drop(mutator);
+        for (out, parent) in promoted {
+            if let Ok(tree) = parse(group.language, &out) {
+                let key = format!("promoted-{}", Uuid::new_v4());
+                debug!("Promoting input to seed {key}");
+                if let Some(dir) = &args.export_corpus {
+                    if let Err(e) = export(dir, &out) {
+                        warn!("{e:#}");
+                    }
+                }
+                parents.insert(key.clone(), parent);
+                corpus.to_mut().insert(key, (out, tree));
+            }
+        }
+    }
+    stats
+}
+
+/// Write up to --dry-run-count mutants to the output directory, without ever
+/// running the target
+fn dry_run(
+    groups: &[Group],
+    args: &Args,
+    weights: &[(glob::Pattern, u32)],
+    dict: &[Vec<u8>],
+    stop: &AtomicBool,
+) -> Result<RunStats> {
+    let start = Instant::now();
+    prepare_output(args)?;
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let sizes = WeightedIndex::new(groups.iter().map(|g| g.files.len())).unwrap();
+    let histogram = histogram::Histogram::new();
+    let mut written = 0;
+    'outer: while written < args.dry_run_count && !stop.load(Ordering::Relaxed) {
+        let group = &groups[rng.sample(&sizes)];
+        let mut mutator = mutator::new(
+            group.language,
+            &group.node_types2,
+            args,
+            &group.files,
+            weights,
+            dict,
+            &mut rng,
+        );
+        let before = written;
+        for _ in 0..args.batch {
+            if written == args.dry_run_count || stop.load(Ordering::Relaxed) {
+                break 'outer;
+            }
+            let Some((out, source)) = mutator.next(&mut rng) else {
+                break;
+            };
+            let path = args.output.join(format!("mutant-{written}.out"));
+            fs::write(&path, &out)
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+            debug!(
+                "Wrote {} ({} bytes, from {})",
+                path.display(),
+                out.len(),
+                source.file
+            );
+            histogram.record(out.len());
+            written += 1;
+        }
+        if written == before {
+            warn!("The corpus didn't yield any mutants");
+            break;
+        }
+    }
+    info!(
+        "Wrote {written} mutants to {} without running the target",
+        args.output.display()
+    );
+    Ok(RunStats {
+        elapsed: start.elapsed(),
+        mutant_sizes: histogram.buckets(),
+        ..RunStats::default()
+    })
+}
+
+/// The seeds that `key` was promoted from, nearest first
+fn ancestors(parents: &HashMap<String, String>, key: &str) -> Vec<String> {
+    let mut ancestors = Vec::new();
+    let mut next = parents.get(key);
+    while let Some(parent) = next {
+        ancestors.push(parent.clone());
+        next = parents.get(parent);
+    }
+    ancestors
+}
+
+/// Save a new seed to a libFuzzer-style corpus directory
+fn export(dir: &Path, input: &[u8]) -> Result<()> {
+    let path = dir.join(sha1_smol::Sha1::from(input).digest().to_string());
+    if !path.exists() {
+        fs::write(&path, input).with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(())
+}
+
+/// Where a fuzzing thread saves its artifacts
+///
+/// Giving each thread its own directory avoids contention when many of them
+/// find crashes at once.
+fn worker_dir(args: &Args, worker: usize) -> PathBuf {
+    if args.flat {
+        args.output.clone()
+    } else {
+        args.output.join(format!("worker-{worker}"))
+    }
+}
+
+/// Create the output directory, refusing to mix in another campaign's files
+/// unless asked to with --force or --resume
+fn prepare_output(args: &Args) -> Result<()> {
+    let dir = &args.output;
+    if dir.exists() && !dir.is_dir() {
+        anyhow::bail!("Output path {} is not a directory", dir.display());
+    }
+    // The log file may already have been opened in there
+    let log_file = args.log_file.as_ref().and_then(|p| p.canonicalize().ok());
+    if dir.is_dir() && !args.force && !args.resume && has_files(dir, log_file.as_deref())? {
+        anyhow::bail!(
+            "Output directory {} is not empty, pass --resume to continue that campaign or --force to add to it anyway",
+            dir.display()
+        );
+    }
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create output directory {}", dir.display()))
+}
+
+/// Whether there are any files under `dir` but `ignore`, not counting empty
+/// directories such as the per-worker ones of a run that found nothing
+fn has_files(dir: &Path, ignore: Option<&Path>) -> Result<bool> {
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read directory {}", dir.display()))?
+    {
+        let entry = entry?;
+        let path = entry.path();
+        if entry.file_type()?.is_dir() {
+            if has_files(&path, ignore)? {
+                return Ok(true);
+            }
+        } else if ignore.is_none_or(|i| path.canonicalize().ok().as_deref() != Some(i)) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Counters shared by all fuzzing threads, used for limits and reporting
+#[derive(Debug)]
+struct Progress {
+    stop: Arc<AtomicBool>,
+    execs: AtomicU64,
+    crashes: AtomicU64,
+    hangs: AtomicU64,
+    ooms: AtomicU64,
+    /// For [`NameScheme::Sequential`]
+    artifacts: AtomicU64,
+    /// For --max-execs-per-sec and --max-total-time
+    start: Instant,
+    /// Sizes of all tests that were run
+    mutant_sizes: histogram::Histogram,
+    /// Sizes of saved crashes, before reduction
+    crash_sizes: histogram::Histogram,
+    /// Saved crashes by the signal that killed the target
+    crash_signals: Mutex<BTreeMap<i32, u64>>,
+    /// Saved crashes by the target's exit code
+    crash_exit_codes: Mutex<BTreeMap<i32, u64>>,
+}
+
+impl Progress {
+    fn new(stop: Arc<AtomicBool>) -> Self {
+        Progress {
+            stop,
+            execs: AtomicU64::new(0),
+            crashes: AtomicU64::new(0),
+            hangs: AtomicU64::new(0),
+            ooms: AtomicU64::new(0),
+            artifacts: AtomicU64::new(0),
+            start: Instant::now(),
+            mutant_sizes: histogram::Histogram::new(),
+            crash_sizes: histogram::Histogram::new(),
+            crash_signals: Mutex::new(BTreeMap::new()),
+            crash_exit_codes: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    fn uptime_ms(&self) -> u64 {
+        u64::try_from(self.start.elapsed().as_millis()).unwrap_or(u64::MAX)
+    }
+
+    /// Sleep until running another test would keep the rate of executions
+    /// under --max-execs-per-sec
+    fn throttle(&self, args: &Args) {
+        if args.max_execs_per_sec == 0 {
+            return;
+        }
+        let execs = self.execs.load(Ordering::Relaxed);
+        let due = Duration::from_secs_f64(execs as f64 / args.max_execs_per_sec as f64);
+        if let Some(wait) = due.checked_sub(self.start.elapsed()) {
+            std::thread::sleep(wait);
+        }
+    }
+
+    fn should_stop(&self, args: &Args) -> bool {
+        let reached = |limit: u64, n: &AtomicU64| limit != 0 && n.load(Ordering::Relaxed) >= limit;
+        self.stop.load(Ordering::Relaxed)
+            || reached(args.runs, &self.execs)
+            || reached(args.max_crashes, &self.crashes)
+            || (args.max_total_time != 0
+                && self.start.elapsed() >= Duration::from_secs(args.max_total_time))
+    }
+}
+
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// One line of `--stats-format json` output
+#[derive(Debug, serde::Serialize)]
+struct Snapshot {
+    execs: u64,
+    execs_per_sec: f64,
+    crashes: u64,
+    hangs: u64,
+    ooms: u64,
+    elapsed_secs: f64,
+    mutant_sizes: Vec<SizeBucket>,
+    crash_sizes: Vec<SizeBucket>,
+    crash_signals: BTreeMap<i32, u64>,
+    crash_exit_codes: BTreeMap<i32, u64>,
+}
+
+/// Periodically print the number of tests run per second, until `done`
+fn report(progress: &Progress, format: StatsFormat, mut out: impl Write, done: &AtomicBool) {
+    let start = Instant::now();
+    let mut last = start;
+    while !done.load(Ordering::Relaxed) {
+        // Sleep in short increments so as not to hold up exiting
+        std::thread::sleep(Duration::from_millis(100));
+        if last.elapsed() < REPORT_INTERVAL {
+            continue;
+        }
+        last = Instant::now();
+        let secs = start.elapsed().as_secs_f64();
+        let execs = progress.execs.load(Ordering::Relaxed);
+        let written = match format {
+            StatsFormat::Human => writeln!(out, "execs/sec: {:.0}", execs as f64 / secs),
+            StatsFormat::Json => {
+                let snapshot = Snapshot {
+                    execs,
+                    execs_per_sec: execs as f64 / secs,
+                    crashes: progress.crashes.load(Ordering::Relaxed),
+                    hangs: progress.hangs.load(Ordering::Relaxed),
+                    ooms: progress.ooms.load(Ordering::Relaxed),
+                    mutant_sizes: progress.mutant_sizes.buckets(),
+                    crash_sizes: progress.crash_sizes.buckets(),
+                    crash_signals: progress.crash_signals.lock().unwrap().clone(),
+                    crash_exit_codes: progress.crash_exit_codes.lock().unwrap().clone(),
+                    elapsed_secs: secs,
+                };
+                serde_json::to_writer(&mut out, &snapshot)
+                    .map_err(std::io::Error::from)
+                    .and_then(|()| writeln!(out))
+            }
+        };
+        if let Err(e) = written {
+            warn!("Failed to write statistics: {e}");
+        }
+    }
+}
+
+/// Warn about fuzzing threads whose heartbeats are more than `timeout` old
+///
+/// A heartbeat is the [`Progress::uptime_ms`] at which the thread last made
+/// progress, or `u64::MAX` once it's finished.
+fn watchdog(progress: &Progress, heartbeats: &[AtomicU64], timeout: Duration, done: &AtomicBool) {
+    let mut stalled = vec![false; heartbeats.len()];
+    while !done.load(Ordering::Relaxed) {
+        std::thread::sleep(Duration::from_millis(100));
+        let now = Duration::from_millis(progress.uptime_ms());
+        for stall in stalls(now, heartbeats, timeout, &mut stalled) {
+            match stall {
+                Stall::Started(worker, idle) => warn!(
+                    "Thread {worker} hasn't made progress in {}s",
+                    idle.as_secs()
+                ),
+                Stall::Ended(worker) => warn!("Thread {worker} is making progress again"),
+            }
+        }
+    }
+}
+
+/// A change in whether a fuzzing thread is making progress
+#[derive(Debug, PartialEq, Eq)]
+enum Stall {
+    /// It's been this long since its last heartbeat
+    Started(usize, Duration),
+    Ended(usize),
+}
+
+/// Which threads started or stopped being `stalled` as of `now`, updating it
+fn stalls(
+    now: Duration,
+    heartbeats: &[AtomicU64],
+    timeout: Duration,
+    stalled: &mut [bool],
+) -> Vec<Stall> {
+    let mut changes = Vec::new();
+    for (worker, (beat, stalled)) in heartbeats.iter().zip(stalled).enumerate() {
+        let idle = now.saturating_sub(Duration::from_millis(beat.load(Ordering::Relaxed)));
+        if idle < timeout {
+            if *stalled {
+                changes.push(Stall::Ended(worker));
+                *stalled = false;
+            }
+        } else if !*stalled {
+            changes.push(Stall::Started(worker, idle));
+            *stalled = true;
+        }
+    }
+    changes
+}
+
+/// Counters kept by each fuzzing thread, summed up at the end of the run
+#[derive(Clone, Debug, Default)]
+struct JobStats {
+    execs: usize,
+    crashes: usize,
+    duplicates: usize,
+    hangs: usize,
+    ooms: usize,
+    /// Times the target couldn't be run at all
+    start_errors: usize,
+    /// Tests not run because of --skip-seen
+    skipped: usize,
+    /// Saved crashes that were reduced, and their sizes before and after
+    reduced: usize,
+    reduced_from: usize,
+    reduced_to: usize,
+    /// Tests generated from each corpus file, with --warn-on-unused-corpus-file
+    uses: BTreeMap<String, usize>,
+}
+
+impl std::ops::AddAssign for JobStats {
+    fn add_assign(&mut self, other: Self) {
+        self.execs += other.execs;
+        self.crashes += other.crashes;
+        self.duplicates += other.duplicates;
+        self.hangs += other.hangs;
+        self.ooms += other.ooms;
+        self.start_errors += other.start_errors;
+        self.skipped += other.skipped;
+        self.reduced += other.reduced;
+        self.reduced_from += other.reduced_from;
+        self.reduced_to += other.reduced_to;
+        for (key, n) in other.uses {
+            *self.uses.entry(key).or_default() += n;
+        }
+    }
+}
+
+/// The corpus files among `keys` that were used fewer than `threshold` times,
+/// with how many times they were
+fn unused_files<'a>(
+    keys: impl Iterator<Item = &'a str>,
+    uses: &BTreeMap<String, usize>,
+    threshold: usize,
+) -> Vec<(&'a str, usize)> {
+    let mut unused: Vec<_> = keys
+        .map(|key| (key, uses.get(key).copied().unwrap_or(0)))
+        .filter(|(_, n)| *n < threshold)
+        .collect();
+    unused.sort_unstable();
+    unused
+}
+
+/// A tree-sitter grammar, see [`run_multi`]
+#[derive(Clone, Copy, Debug)]
+pub struct Grammar<'a> {
+    /// Parse corpus files with these extensions using this grammar; all files
+    /// if empty
+    pub extensions: &'a [&'a str],
+    pub language: Language,
+    /// The grammar's `node-types.json`
+    pub node_types: &'a str,
+}
+
+impl Grammar<'_> {
+    /// Index of the first grammar that should parse `path`
+    fn route(grammars: &[Grammar<'_>], path: &Path) -> Option<usize> {
+        let ext = corpus::extension(path).unwrap_or_default();
+        grammars
+            .iter()
+            .position(|g| g.extensions.is_empty() || g.extensions.iter().any(|e| ext == *e))
+    }
+}
+
+/// The corpus files for one grammar
+struct Group {
+    language: Language,
+    // HACK: there should be another crate that deals with this...
+    node_types1: treereduce::NodeTypes,
+    node_types2: tree_splicer::node_types::NodeTypes,
+    files: HashMap<String, (Vec<u8>, Tree)>,
+}
+
+/// What happened during a call to [`run`]
+#[derive(Clone, Debug, Default)]
+pub struct RunStats {
+    pub execs: usize,
+    pub crashes: usize,
+    pub duplicates: usize,
+    /// Tests that weren't run because of --skip-seen
+    pub skipped: usize,
+    pub hangs: usize,
+    pub ooms: usize,
+    pub elapsed: Duration,
+    /// Sizes of the tests that were run
+    pub mutant_sizes: Vec<SizeBucket>,
+    /// Sizes of the crashes that were saved, before reduction
+    pub crash_sizes: Vec<SizeBucket>,
+    /// Number of crashes that were saved for each signal
+    pub crash_signals: BTreeMap<i32, u64>,
+    /// Number of crashes that were saved for each exit code
+    pub crash_exit_codes: BTreeMap<i32, u64>,
+    /// Saved crashes that were reduced
+    pub reduced: usize,
+    /// Total size of the reduced crashes before reduction
+    pub reduced_from: usize,
+    /// Total size of the reduced crashes after reduction
+    pub reduced_to: usize,
+}
+
+/// Find the program that the check would run, like the shell does
+///
+/// Relative paths are resolved in the target's working directory.
+fn find_program(program: &str, cwd: Option<&Path>) -> Result<PathBuf> {
+    let candidates: Vec<PathBuf> = if program.contains('/') {
+        vec![cwd.map_or_else(|| PathBuf::from(program), |dir| dir.join(program))]
+    } else {
+        let path = std::env::var_os("PATH").unwrap_or_default();
+        std::env::split_paths(&path)
+            .map(|dir| dir.join(program))
+            .collect()
+    };
+    let executable = |p: &Path| {
+        fs::metadata(p).is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+    if let Some(found) = candidates.iter().find(|p| executable(p)) {
+        return Ok(found.clone());
+    }
+    match candidates.iter().find(|p| p.exists()) {
+        Some(p) => anyhow::bail!("Check command {} is not executable", p.display()),
+        None if program.contains('/') => anyhow::bail!("Check command {program} does not exist"),
+        None => anyhow::bail!("Check command {program} was not found on PATH"),
+    }
+}
+
+/// Build the interestingness check for `check` from the options in `args`
+fn check_from_args(args: &Args, check: Vec<String>) -> Result<CmdCheck> {
+    // The input is still passed the same way, since the wrapper just runs the
+    // rest of its arguments
+    let check: Vec<String> = args
+        .wrapper
+        .iter()
+        .flat_map(|w| w.split_whitespace().map(String::from))
+        .chain(check)
+        .collect();
+    if let Some(dir) = &args.target_cwd {
+        if !dir.is_dir() {
+            anyhow::bail!(
+                "Target working directory {} is not a directory",
+                dir.display()
+            );
+        }
+    }
+    if let Some(dir) = &args.tmp_dir {
+        if !dir.is_dir() {
+            anyhow::bail!("Temporary directory {} is not a directory", dir.display());
+        }
+    }
+    if let Some(program) = check.first() {
+        find_program(program, args.target_cwd.as_deref())?;
+    }
+    if args.persistent && check.iter().any(|a| a.contains("@@")) {
+        anyhow::bail!("Persistent targets read inputs from stdin, so the check can't use @@");
+    }
+    Ok(make_check(
+        args.debug,
+        Duration::from_millis(args.timeout),
+        check,
+        args.interesting_exit_code.clone(),
+        args.any_nonzero_exit,
+        !args.no_default_signal_codes,
+        args.ignore_signal.clone(),
+        args.interesting_stdout.clone(),
+        args.interesting_stderr.clone(),
         args.uninteresting_stdout.clone(),
         args.uninteresting_stderr.clone(),
-    )?;
-    let node_types1 = treereduce::NodeTypes::new(node_types_json_str).unwrap();
-    let node_types2 = tree_splicer::node_types::NodeTypes::new(node_types_json_str).unwrap();
+        args.interesting_output.clone(),
+        args.uninteresting_output.clone(),
+        &args.also_interesting_stderr,
+        &args.also_uninteresting_stderr,
+        args.regex_case_insensitive,
+        args.regex_multiline,
+        args.target_cwd.clone(),
+        args.env.clone(),
+        args.tmp_dir.clone(),
+        args.interesting_output_bytes,
+        args.mem_limit,
+    )?
+    .with_persistent(args.persistent)
+    .with_max_output_bytes(args.max_output_bytes)
+    .with_rlimit_nofile(args.rlimit_nofile)
+    .with_nice(args.nice))
+}
+
+/// Exit codes: 0 if the run went fine, 1 if it found crashes and
+/// `--fail-on-crash` was given, 2 if tree-crasher was misconfigured. The
+/// `repro` subcommand exits with 1 if the input is no longer interesting.
+pub fn main(language: Language, node_types_json_str: &'static str) -> Result<()> {
+    let args = parse_args();
+    if args.grammar.is_some() || args.node_types.is_some() {
+        warn!("--grammar and --node-types are only used by tree-crasher-dynamic");
+    }
+    let grammar = Grammar {
+        extensions: &[],
+        language,
+        node_types: node_types_json_str,
+    };
+    fuzz(&[grammar], args)
+}
+
+/// Like [`main`], but for a corpus that mixes languages
+///
+/// Each corpus file is parsed with the first grammar that claims its
+/// extension, and only spliced with files of the same language.
+pub fn main_multi(grammars: &[Grammar<'_>]) -> Result<()> {
+    let args = parse_args();
+    fuzz(grammars, args)
+}
+
+/// Like [`main`], but loads the grammar given by `--grammar` and
+/// `--node-types` at runtime
+pub fn main_dynamic() -> Result<()> {
+    let args = parse_args();
+    let (language, node_types_json) = match dynamic::load(&args) {
+        Err(e) => {
+            error!("{e:?}");
+            std::process::exit(2);
+        }
+        Ok(grammar) => grammar,
+    };
+    let grammar = Grammar {
+        extensions: &[],
+        language,
+        node_types: &node_types_json,
+    };
+    fuzz(&[grammar], args)
+}
+
+fn parse_args() -> Args {
+    match config::parse_args() {
+        Err(e) => {
+            eprintln!("Error: {e:?}");
+            std::process::exit(2);
+        }
+        Ok(args) => {
+            if let Err(e) = logger::init(log_level(&args), args.log_file.as_deref()) {
+                eprintln!("Error: {e:?}");
+                std::process::exit(2);
+            }
+            args
+        }
+    }
+}
 
+/// How much to log, according to -v, -q, and --debug
+fn log_level(args: &Args) -> log::LevelFilter {
+    // --debug shows what tree-crasher is doing, as -v does
+    let level = args.verbose.log_level_filter();
     if args.debug {
-        eprintln!("Spawning threads...");
+        level.max(log::LevelFilter::Debug)
+    } else {
+        level
+    }
+}
+
+fn fuzz(grammars: &[Grammar<'_>], args: Args) -> Result<()> {
+    let stop = Arc::new(AtomicBool::new(false));
+    // Can only fail if main is called more than once
+    STOP.set(stop.clone()).ok();
+    // Threads finish their current test (and its reduction) before exiting
+    let handler: extern "C" fn(libc::c_int) = on_sigint;
+    // SAFETY: The handler only touches an atomic and calls signal.
+    unsafe { libc::signal(libc::SIGINT, handler as libc::sighandler_t) };
+
+    let result = run_multi(grammars, args.clone(), stop);
+    let code = exit_code(&args, &result);
+    let stats = match result {
+        Err(e) => {
+            error!("{e:?}");
+            std::process::exit(code);
+        }
+        Ok(stats) => stats,
+    };
+    if let Some(Command::Repro { .. }) = args.command {
+        std::process::exit(code);
+    }
+    if args.dry_run {
+        print_sizes("Sizes of mutants", &stats.mutant_sizes);
+        return Ok(());
+    }
+    if args.print_mutant || args.explain.is_some() || args.command.is_some() {
+        return Ok(());
+    }
+    info!(
+        "Ran {} tests in {:.1}s, found {} crashes",
+        stats.execs,
+        stats.elapsed.as_secs_f64(),
+        stats.crashes
+    );
+    if args.dedup != Dedup::Off {
+        info!("Skipped {} duplicate crashes", stats.duplicates);
+    }
+    if args.skip_seen {
+        let generated = stats.execs + stats.skipped;
+        info!(
+            "Skipped {} repeated tests ({:.1}%)",
+            stats.skipped,
+            100.0 * stats.skipped as f64 / generated.max(1) as f64
+        );
+    }
+    if args.report_hangs {
+        info!("Found {} hangs", stats.hangs);
+    }
+    if args.report_oom || stats.ooms > 0 {
+        info!("Found {} OOM kills", stats.ooms);
+    }
+    if stats.reduced > 0 {
+        info!(
+            "Reduced {} crashes from {} to {} bytes in total ({:.1}% smaller)",
+            stats.reduced,
+            stats.reduced_from,
+            stats.reduced_to,
+            percent_smaller(stats.reduced_from, stats.reduced_to)
+        );
+    }
+    print_counts("Crashes by signal", "signal", &stats.crash_signals);
+    print_counts("Crashes by exit code", "exit code", &stats.crash_exit_codes);
+    print_sizes("Sizes of tests", &stats.mutant_sizes);
+    print_sizes("Sizes of crashes", &stats.crash_sizes);
+    if code != 0 {
+        std::process::exit(code);
+    }
+    Ok(())
+}
+
+/// How the process should exit after a run
+///
+/// That's 2 if the run couldn't be set up or the target couldn't be run at
+/// all, 1 if crashes were found with --fail-on-crash (or the input didn't
+/// reproduce with `repro`), and 0 otherwise.
+fn exit_code(args: &Args, result: &Result<RunStats>) -> i32 {
+    match result {
+        Err(_) => 2,
+        Ok(stats) if matches!(args.command, Some(Command::Repro { .. })) => {
+            i32::from(stats.crashes == 0)
+        }
+        Ok(stats) if stats.crashes > 0 && args.fail_on_crash => 1,
+        Ok(_) => 0,
+    }
+}
+
+fn percent_smaller(before: usize, after: usize) -> f64 {
+    100.0 * (1.0 - after as f64 / before.max(1) as f64)
+}
+
+fn print_counts(title: &str, what: &str, counts: &BTreeMap<i32, u64>) {
+    if counts.is_empty() {
+        return;
+    }
+    info!("{title}:");
+    for (key, n) in counts {
+        info!("  {what} {key:<4} {n}");
+    }
+}
+
+fn print_sizes(title: &str, buckets: &[SizeBucket]) {
+    if buckets.is_empty() {
+        return;
+    }
+    info!("{title} (bytes):");
+    for b in buckets {
+        info!("  {:>8}-{:<8} {}", b.min, b.max, b.count);
+    }
+}
+
+/// Fuzz until `stop` is set or a limit in `args` is reached
+pub fn run(
+    language: Language,
+    node_types_json_str: &str,
+    args: Args,
+    stop: Arc<AtomicBool>,
+) -> Result<RunStats> {
+    let grammar = Grammar {
+        extensions: &[],
+        language,
+        node_types: node_types_json_str,
+    };
+    run_multi(&[grammar], args, stop)
+}
+
+/// Like [`run`], but with a corpus that mixes languages
+pub fn run_multi(grammars: &[Grammar<'_>], args: Args, stop: Arc<AtomicBool>) -> Result<RunStats> {
+    let args = &args;
+    debug_assert!(args.interesting_stdout.is_some() || args.uninteresting_stdout.is_none());
+    debug_assert!(args.interesting_stderr.is_some() || args.uninteresting_stderr.is_none());
+    debug_assert!(args.interesting_output.is_some() || args.uninteresting_output.is_none());
+
+    debug!("Loading testcases...");
+    match &args.command {
+        Some(Command::MinimizeCorpus { files, output }) => {
+            let paths = corpus::find(files, args)?;
+            let mut found = 0;
+            for (i, grammar) in grammars.iter().enumerate() {
+                let route = |p: &Path| Grammar::route(grammars, p) == Some(i);
+                found += minimize::minimize(grammar.language, args, files, &paths, output, &route)?;
+            }
+            if found == 0 {
+                anyhow::bail!("No files provided.");
+            }
+            return Ok(RunStats::default());
+        }
+        Some(Command::Repro { file, check }) => {
+            let chk = check_from_args(args, check.clone())?;
+            let reproduced = repro(args, &chk, file)?;
+            return Ok(RunStats {
+                execs: 1,
+                crashes: usize::from(reproduced),
+                ..RunStats::default()
+            });
+        }
+        Some(Command::Reduce {
+            file,
+            output,
+            check,
+        }) => {
+            let grammar = Grammar::route(grammars, file)
+                .map(|i| &grammars[i])
+                .with_context(|| format!("No grammar for {}", file.display()))?;
+            let chk = check_from_args(args, check.clone())?;
+            let node_types = treereduce::NodeTypes::new(grammar.node_types).unwrap();
+            let inp = read_file(file)?;
+            let tree = parse(grammar.language, &inp)?;
+            let Some((reduced, attempt)) =
+                reduce(grammar.language, &node_types, &chk, &tree, &inp, args)
+            else {
+                anyhow::bail!("Failed to reduce {}", file.display());
+            };
+            info!(
+                "Reduced from {} to {} bytes with {} settings",
+                inp.len(),
+                reduced.len(),
+                REDUCE_ATTEMPTS[attempt].name
+            );
+            fs::write(output, reduced)
+                .with_context(|| format!("Failed to write {}", output.display()))?;
+            return Ok(RunStats::default());
+        }
+        None => (),
     }
+    // Only subcommands can do without
+    let dir = args.files.as_deref().context("No files provided.")?;
+    let paths = corpus::find(Path::new(dir), args)?;
+    let mut groups = Vec::new();
+    for (i, grammar) in grammars.iter().enumerate() {
+        let route = |p: &Path| Grammar::route(grammars, p) == Some(i);
+        let files = corpus::load(grammar.language, &paths, args, &route)?;
+        if !files.is_empty() {
+            groups.push(Group {
+                language: grammar.language,
+                node_types1: treereduce::NodeTypes::new(grammar.node_types).unwrap(),
+                node_types2: tree_splicer::node_types::NodeTypes::new(grammar.node_types).unwrap(),
+                files,
+            });
+        }
+    }
+    if groups.is_empty() {
+        match paths.len() {
+            0 if dir == "-" => anyhow::bail!("No corpus files were listed on stdin"),
+            0 => anyhow::bail!("Corpus directory {dir} has no files"),
+            n => anyhow::bail!(
+                "None of the {n} files in corpus directory {dir} could be used, see above"
+            ),
+        }
+    }
+    let weights = match &args.weights {
+        Some(path) => corpus::load_weights(path)?,
+        None => Vec::new(),
+    };
+    let dict = match &args.dict {
+        Some(path) => {
+            let tokens = dict::load(path)?;
+            if tokens.is_empty() {
+                warn!("Dictionary {} has no tokens", path.display());
+            }
+            tokens
+        }
+        None => Vec::new(),
+    };
+
+    if args.print_mutant {
+        let Group {
+            language,
+            node_types2,
+            files,
+            ..
+        } = &groups[0];
+        let config = Config {
+            chaos: args.chaos.unwrap_or(DEFAULT_CHAOS),
+            deletions: args.deletions.unwrap_or(DEFAULT_DELETIONS),
+            language: *language,
+            inter_splices: args.mutations.unwrap_or(DEFAULT_MUTATIONS),
+            node_types: node_types2.clone(),
+            max_size: args.max_size,
+            reparse: args.reparse.unwrap_or(usize::MAX),
+            seed: args.seed,
+        };
+        let mutant = Splicer::new(config, files)
+            .next()
+            .context("Failed to generate a mutant")?;
+        let mut rng = StdRng::seed_from_u64(args.seed);
+        let mutant = dict::maybe_inject(*language, &mut rng, &dict, mutant, args.max_size);
+        std::io::stdout()
+            .write_all(&mutant)
+            .context("Failed to print mutant")?;
+        return Ok(RunStats::default());
+    }
+
+    if args.dry_run {
+        return dry_run(&groups, args, &weights, &dict, &stop);
+    }
+
+    let chk = check_from_args(args, args.check.clone())?;
+    if let Some(path) = &args.explain {
+        explain(&chk, path)?;
+        return Ok(RunStats::default());
+    }
+
+    prepare_output(args)?;
+
+    if let Some(dir) = &args.export_corpus {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+
+    // Distinguishes artifacts from different machines sharing an output
+    let origin = match &args.shard {
+        Some(shard) => format!("{}-{shard}", hostname()),
+        None => hostname(),
+    };
+
+    let mut signatures = Signatures::new(args.max_signatures);
+    let progress = Progress::new(stop);
+    if args.resume {
+        let mut resumed = metadata::Resumed::default();
+        metadata::scan(&args.output, &mut resumed)?;
+        info!(
+            "Resuming with {} known crash signatures",
+            resumed.signatures.len()
+        );
+        for sig in resumed.signatures {
+            signatures.insert(sig);
+        }
+        progress
+            .artifacts
+            .store(resumed.last_artifact, Ordering::Relaxed);
+    }
+    let signatures = Mutex::new(signatures);
+
+    debug!("Spawning threads...");
     #[cfg(not(feature = "radamsa"))]
     let jobs = if args.debug { 1 } else { args.jobs };
     #[cfg(feature = "radamsa")]
     let jobs = if args.debug {
         if args.jobs != 1 {
-            eprintln!("[WARN] Radamsa can only be used with one thread.");
+            warn!("Radamsa can only be used with one thread.");
         }
         1
     } else {
         args.jobs
     };
-    std::thread::scope(|s| {
-        for _ in 0..jobs {
-            s.spawn(|| {
-                job(
-                    language,
-                    &node_types1,
-                    &node_types2,
-                    &args,
-                    &files,
-                    chk.clone(),
-                )
-            });
+    let stats_out: Box<dyn Write + Send> = match &args.stats_file {
+        Some(path) => Box::new(
+            fs::File::create(path)
+                .with_context(|| format!("Failed to create {}", path.display()))?,
+        ),
+        None => Box::new(std::io::stdout()),
+    };
+    let start = Instant::now();
+    let done = AtomicBool::new(false);
+    if !args.flat {
+        for worker in 0..jobs {
+            let dir = worker_dir(args, worker);
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+        }
+    }
+    #[cfg(feature = "metrics")]
+    let metrics_listener = match &args.metrics_addr {
+        Some(addr) => Some(
+            std::net::TcpListener::bind(addr)
+                .with_context(|| format!("Failed to listen for metrics on {addr}"))?,
+        ),
+        None => None,
+    };
+    let cpus = if args.pin_cpus {
+        allowed_cpus().unwrap_or_else(|e| {
+            warn!("Not pinning threads to CPUs: {e}");
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+    if jobs > cpus.len() && !cpus.is_empty() {
+        warn!(
+            "More threads ({jobs}) than CPUs ({}), some will share",
+            cpus.len()
+        );
+    }
+    // For --watchdog-timeout
+    let heartbeats: Vec<_> = (0..jobs)
+        .map(|_| AtomicU64::new(progress.uptime_ms()))
+        .collect();
+    let (totals, panicked) = std::thread::scope(|s| {
+        // Borrow everything here so that each thread only moves its index
+        let (groups, weights, dict) = (&groups, &weights, &dict);
+        let (chk, origin) = (&chk, &origin);
+        let (signatures, progress) = (&signatures, &progress);
+        let cpus = &cpus;
+        let heartbeats = &heartbeats;
+        let handles: Vec<_> = (0..jobs)
+            .map(|worker| {
+                s.spawn(move || {
+                    if !cpus.is_empty() {
+                        let cpu = cpus[worker % cpus.len()];
+                        if let Err(e) = pin_to_cpu(cpu) {
+                            warn!("Failed to pin thread {worker} to CPU {cpu}: {e}");
+                        }
+                    }
+                    let stats = job(
+                        groups,
+                        args,
+                        worker,
+                        weights,
+                        dict,
+                        chk.clone(),
+                        origin,
+                        signatures,
+                        progress,
+                        &heartbeats[worker],
+                    );
+                    heartbeats[worker].store(u64::MAX, Ordering::Relaxed);
+                    stats
+                })
+            })
+            .collect();
+        s.spawn(|| report(progress, args.stats_format, stats_out, &done));
+        #[cfg(feature = "metrics")]
+        if let Some(listener) = &metrics_listener {
+            let done = &done;
+            s.spawn(move || metrics::serve(listener, progress, done));
+        }
+        if let Some(secs) = args.watchdog_timeout {
+            let done = &done;
+            s.spawn(move || watchdog(progress, heartbeats, Duration::from_secs(secs), done));
+        }
+        let mut totals = JobStats::default();
+        let mut panicked = 0;
+        for handle in handles {
+            match handle.join() {
+                Ok(stats) => totals += stats,
+                Err(_) => panicked += 1,
+            }
         }
+        done.store(true, Ordering::Relaxed);
+        (totals, panicked)
     });
 
-    Ok(())
+    if panicked > 0 {
+        warn!("{panicked} of {jobs} fuzzing threads panicked");
+    }
+    if totals.execs == 0 && totals.start_errors > 0 {
+        anyhow::bail!("Failed to run the interestingness check");
+    }
+    if args.warn_on_unused_corpus_file {
+        // Seeds promoted during the run aren't worth reporting
+        let keys = groups
+            .iter()
+            .flat_map(|g| g.files.keys().map(String::as_str));
+        let unused = unused_files(keys, &totals.uses, args.unused_threshold);
+        if !unused.is_empty() {
+            warn!(
+                "{} corpus files were used fewer than {} times:",
+                unused.len(),
+                args.unused_threshold
+            );
+            for (key, n) in unused {
+                warn!("  {key}: {n}");
+            }
+        }
+    }
+    Ok(RunStats {
+        execs: totals.execs,
+        crashes: totals.crashes,
+        duplicates: totals.duplicates,
+        skipped: totals.skipped,
+        hangs: totals.hangs,
+        ooms: totals.ooms,
+        elapsed: start.elapsed(),
+        mutant_sizes: progress.mutant_sizes.buckets(),
+        crash_sizes: progress.crash_sizes.buckets(),
+        crash_signals: progress.crash_signals.into_inner().unwrap(),
+        crash_exit_codes: progress.crash_exit_codes.into_inner().unwrap(),
+        reduced: totals.reduced,
+        reduced_from: totals.reduced_from,
+        reduced_to: totals.reduced_to,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{self, args, corpus, sh};
+
+    /// A run on the C files in `corpus` on one thread, saving to `out`
+    fn run_c(
+        corpus: &Path,
+        out: &Path,
+        flags: &[&str],
+        check: &[&str],
+    ) -> (Args, Result<RunStats>) {
+        let (corpus, out) = (corpus.to_string_lossy(), out.to_string_lossy());
+        let argv: Vec<&str> = ["--no-reduce", "-j", "1", "-o", out.as_ref()]
+            .into_iter()
+            .chain(flags.iter().copied())
+            .chain([corpus.as_ref(), "--"])
+            .chain(check.iter().copied())
+            .collect();
+        let args = args(&argv);
+        let result = testing::run(&args);
+        (args, result)
+    }
+
+    /// Exit code of a short run on a C corpus
+    fn exit_code_of(flags: &[&str], check: &[&str]) -> i32 {
+        let dir = corpus(&[
+            ("a.c", "int f(int x) { return x + 1; }"),
+            ("b.c", "int main(void) { int y = 2; return f(y) * 3; }"),
+        ]);
+        let flags: Vec<&str> = ["--runs", "4"]
+            .into_iter()
+            .chain(flags.iter().copied())
+            .collect();
+        let (args, result) = run_c(dir.path(), &dir.path().join("out"), &flags, check);
+        exit_code(&args, &result)
+    }
+
+    /// The files under `dir` with this extension
+    fn files_with(dir: &Path, ext: &str) -> Vec<PathBuf> {
+        let mut found = Vec::new();
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                found.extend(files_with(&path, ext));
+            } else if path.extension().is_some_and(|e| e == ext) {
+                found.push(path);
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn metadata_names_the_source_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let seeds = corpus(&[("only.c", "int f(int x) { return x + 1; }")]);
+        let flags = ["--runs", "1", "--interesting-exit-code", "1"];
+        let (_, result) = run_c(seeds.path(), dir.path(), &flags, &["false"]);
+        assert_eq!(result.unwrap().crashes, 1);
+        let json = files_with(dir.path(), "json");
+        assert_eq!(json.len(), 1);
+        let metadata: serde_json::Value =
+            serde_json::from_slice(&fs::read(&json[0]).unwrap()).unwrap();
+        let only = seeds.path().join("only.c");
+        assert_eq!(metadata["source_file"], *only.to_string_lossy());
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(exit_code_of(&[], &["true"]), 0);
+        assert_eq!(exit_code_of(&[], &["false"]), 0);
+        let crash = ["--fail-on-crash", "--interesting-exit-code", "1"];
+        assert_eq!(exit_code_of(&crash, &["false"]), 1);
+        assert_eq!(exit_code_of(&crash, &["true"]), 0);
+        assert_eq!(exit_code_of(&["--interesting-stdout", "("], &["true"]), 2);
+        assert_eq!(exit_code_of(&[], &["/nonexistent/target"]), 2);
+    }
+
+    /// The check for a command line
+    fn check_of(argv: &[&str]) -> CmdCheck {
+        let args = args(argv);
+        check_from_args(&args, args.check.clone()).unwrap()
+    }
+
+    #[test]
+    fn temp_files_are_removed_after_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let tmp = dir.path().to_string_lossy();
+        for marker in ["@@", "@@dir"] {
+            let chk = check_of(&[
+                "--tmp-dir",
+                &tmp,
+                "--timeout",
+                "100",
+                "corpus",
+                "--",
+                "sh",
+                "-c",
+                "exec sleep 60",
+                "sh",
+                marker,
+            ]);
+            let (status, output) = chk.run(b"int x;").unwrap();
+            assert_eq!(chk.classify(status, &output), Outcome::Timeout);
+            assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0, "{marker}");
+        }
+    }
+
+    #[test]
+    fn rlimit_nofile() {
+        let chk = check_of(&[
+            "--rlimit-nofile",
+            "17",
+            "corpus",
+            "--",
+            "sh",
+            "-c",
+            "ulimit -n",
+        ]);
+        let (_, output) = chk.run(b"").unwrap();
+        assert_eq!(output.stdout, b"17\n");
+    }
+
+    #[test]
+    fn wrapper_runs_the_target() {
+        let chk = check_of(&["--wrapper", "echo wrapped", "corpus", "--", "cat", "@@"]);
+        assert_eq!(chk.argv(), ["echo", "wrapped", "cat", "@@"]);
+        let (_, output) = chk.run(b"").unwrap();
+        assert!(output.stdout.starts_with(b"wrapped cat /"));
+    }
+
+    #[test]
+    fn nice() {
+        // A level, not an increment, so it doesn't depend on ours
+        let chk = check_of(&["--nice", "5", "corpus", "--", "nice"]);
+        let (_, output) = chk.run(b"").unwrap();
+        assert_eq!(output.stdout, b"5\n");
+    }
+
+    #[test]
+    fn default_patterns() {
+        assert!(DEFAULT_INTERESTING_PATTERNS.contains(&"AddressSanitizer"));
+        let interesting = regex::Regex::new(&DEFAULT_INTERESTING_PATTERNS.join("|")).unwrap();
+        assert!(interesting.is_match("==1==ERROR: AddressSanitizer: SEGV"));
+        let uninteresting = regex::Regex::new(&DEFAULT_UNINTERESTING_PATTERNS.join("|")).unwrap();
+        assert!(uninteresting.is_match("SyntaxError: unexpected token"));
+        assert!(!uninteresting.is_match("==1==ERROR: AddressSanitizer: SEGV"));
+    }
+
+    #[test]
+    fn dry_run_does_not_run_the_target() {
+        let dir = tempfile::tempdir().unwrap();
+        let (out, marker) = (dir.path().join("out"), dir.path().join("ran"));
+        let seeds = corpus(&[("a.c", "int f(int x) { return x + 1; }")]);
+        let flags = ["--dry-run", "--dry-run-count", "3"];
+        let ran = marker.to_string_lossy();
+        let check = ["sh", "-c", "touch \"$1\"", "sh", ran.as_ref()];
+        let (_, result) = run_c(seeds.path(), &out, &flags, &check);
+        assert_eq!(result.unwrap().execs, 0);
+        assert!(!marker.exists());
+        assert_eq!(files_with(&out, "out").len(), 3);
+    }
+
+    #[test]
+    fn same_seed_same_mutants() {
+        let dir = tempfile::tempdir().unwrap();
+        let seeds = corpus(&[
+            ("a.c", "int f(int x) { return x + 1; }"),
+            ("b.c", "int main(void) { int y = 2; return f(y) * 3; }"),
+        ]);
+        let flags = ["--dry-run", "--dry-run-count", "16"];
+        let flags = flags
+            .into_iter()
+            .chain(["--seed", "42", "--corpus-order", "sorted"]);
+        let flags: Vec<_> = flags.collect();
+        let mutants = |out: &Path| {
+            run_c(seeds.path(), out, &flags, &["true"]).1.unwrap();
+            (0..16)
+                .map(|i| fs::read(out.join(format!("mutant-{i}.out"))).unwrap())
+                .collect::<Vec<_>>()
+        };
+        let first = mutants(&dir.path().join("first"));
+        assert_eq!(first, mutants(&dir.path().join("second")));
+    }
+
+    #[test]
+    fn hook_runs_for_new_crashes_only() {
+        let dir = tempfile::tempdir().unwrap();
+        let hooks = dir.path().join("hooks");
+        let seeds = corpus(&[("a.c", "int f(int x) { return x + 1; }")]);
+        let hook = format!("echo \"$1\" >> '{}'", hooks.display());
+        let flags = [
+            "--runs",
+            "3",
+            "--interesting-exit-code",
+            "1",
+            "--dedup",
+            "signal",
+            "--on-crash",
+            hook.as_str(),
+        ];
+        let (_, result) = run_c(seeds.path(), &dir.path().join("out"), &flags, &["false"]);
+        let stats = result.unwrap();
+        assert_eq!(stats.crashes, 1);
+        assert!(stats.duplicates > 0);
+        // The hook runs in the background
+        let start = Instant::now();
+        while !hooks.exists() && start.elapsed() < Duration::from_secs(10) {
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        std::thread::sleep(Duration::from_millis(200));
+        let lines = fs::read_to_string(&hooks).unwrap();
+        assert_eq!(lines.lines().count(), 1);
+        assert!(Path::new(lines.trim_end()).exists());
+    }
+
+    #[test]
+    fn known_crashes_are_not_reduced() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let runs = dir.path().join("runs");
+        let seeds = corpus(&[("a.c", "int f(int x) { return x + 1; }")]);
+        let flags = [
+            "--runs",
+            "1",
+            "--interesting-exit-code",
+            "1",
+            "--dedup",
+            "signal",
+        ];
+        let (_, result) = run_c(seeds.path(), &out, &flags, &["false"]);
+        assert_eq!(result.unwrap().crashes, 1);
+        // Same signature, but now with reduction
+        let (out, seeds) = (out.to_string_lossy(), seeds.path().to_string_lossy());
+        let script = format!("echo >> '{}'; exit 1", runs.display());
+        let argv: Vec<&str> = ["-j", "1", "--resume", "-o", out.as_ref()]
+            .into_iter()
+            .chain(flags)
+            .chain([seeds.as_ref(), "--", "sh", "-c", script.as_str()])
+            .collect();
+        let stats = testing::run(&args(&argv)).unwrap();
+        assert_eq!(stats.crashes, 0);
+        assert_eq!(stats.duplicates, 1);
+        assert_eq!(fs::read_to_string(&runs).unwrap().lines().count(), 1);
+    }
+
+    #[test]
+    fn dedup_off_skips_signatures() {
+        let dir = tempfile::tempdir().unwrap();
+        let seeds = corpus(&[("a.c", "int f(int x) { return x + 1; }")]);
+        let flags = [
+            "--runs",
+            "3",
+            "--interesting-exit-code",
+            "1",
+            "--dedup",
+            "off",
+        ];
+        let (_, result) = run_c(seeds.path(), dir.path(), &flags, &["false"]);
+        let stats = result.unwrap();
+        assert_eq!((stats.crashes, stats.duplicates), (3, 0));
+        let json = files_with(dir.path(), "json");
+        assert_eq!(json.len(), 3);
+        for json in json {
+            let metadata: serde_json::Value =
+                serde_json::from_slice(&fs::read(&json).unwrap()).unwrap();
+            assert!(metadata["signature"].is_null());
+        }
+    }
+
+    #[test]
+    fn populated_output_needs_force() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().to_string_lossy();
+        let prepare = |flags: &[&str]| {
+            let argv: Vec<&str> = ["-o", out.as_ref()]
+                .into_iter()
+                .chain(flags.iter().copied())
+                .chain(["corpus", "true"])
+                .collect();
+            prepare_output(&args(&argv))
+        };
+        fs::create_dir(dir.path().join("worker-0")).unwrap();
+        assert!(prepare(&[]).is_ok());
+        fs::write(dir.path().join("worker-0").join("crash.out"), "int x;").unwrap();
+        let e = prepare(&[]).unwrap_err();
+        assert!(e.to_string().contains("is not empty"), "{e}");
+        assert!(prepare(&["--force"]).is_ok());
+        assert!(prepare(&["--resume"]).is_ok());
+    }
+
+    #[test]
+    fn watchdog_notices_stalls() {
+        let secs = Duration::from_secs;
+        // The last thread has finished
+        let heartbeats = [
+            AtomicU64::new(0),
+            AtomicU64::new(5500),
+            AtomicU64::new(u64::MAX),
+        ];
+        let mut stalled = [false; 3];
+        let mut stalls = |now| stalls(now, &heartbeats, secs(2), &mut stalled);
+        assert!(stalls(secs(1)).is_empty());
+        assert_eq!(stalls(secs(6)), [Stall::Started(0, secs(6))]);
+        // Only reported once
+        assert!(stalls(secs(7)).is_empty());
+        heartbeats[0].store(7000, Ordering::Relaxed);
+        assert_eq!(
+            stalls(secs(8)),
+            [
+                Stall::Ended(0),
+                Stall::Started(1, Duration::from_millis(2500))
+            ]
+        );
+    }
+
+    #[test]
+    fn keep_modes() {
+        assert!(keep_raw(Keep::All, Some(true)));
+        assert!(keep_raw(Keep::Raw, None));
+        assert!(!keep_raw(Keep::Reduced, Some(true)));
+        // Reduction failed, or its result didn't crash
+        assert!(keep_raw(Keep::Reduced, None));
+        assert!(keep_raw(Keep::Reduced, Some(false)));
+    }
+
+    #[test]
+    fn keep_saves_inputs() {
+        let seeds = corpus(&[("a.c", "int f(int x) { return x + 1; }")]);
+        let seeds = seeds.path().to_string_lossy();
+        // treereduce counts its idle threads in a static, so only the first
+        // reduction in a process does anything; keep_modes covers the choice
+        // once there is a reduction.
+        for keep in ["all", "reduced", "raw"] {
+            let dir = tempfile::tempdir().unwrap();
+            let out = dir.path().to_string_lossy();
+            let flags = ["-j", "1", "--runs", "1", "--interesting-exit-code", "1"];
+            let argv: Vec<&str> = flags
+                .into_iter()
+                .chain([
+                    "--no-reduce",
+                    "--keep",
+                    keep,
+                    "-o",
+                    out.as_ref(),
+                    seeds.as_ref(),
+                    "--",
+                    "false",
+                ])
+                .collect();
+            assert_eq!(testing::run(&args(&argv)).unwrap().crashes, 1);
+            // Without a reduction, even --keep reduced saves the raw input
+            let saved = files_with(dir.path(), "out");
+            assert_eq!(saved.len(), 1, "{keep}");
+            assert!(!saved[0].to_string_lossy().contains(".reduced"), "{keep}");
+        }
+    }
+
+    #[test]
+    fn log_levels() {
+        let level = |flags: &[&str]| {
+            let argv: Vec<_> = flags.iter().copied().chain(["corpus", "true"]).collect();
+            log_level(&args(&argv))
+        };
+        assert_eq!(level(&[]), log::LevelFilter::Info);
+        assert_eq!(level(&["-q"]), log::LevelFilter::Warn);
+        assert_eq!(level(&["-v"]), log::LevelFilter::Debug);
+        assert_eq!(level(&["--debug"]), log::LevelFilter::Debug);
+        assert_eq!(level(&["-q", "--debug"]), log::LevelFilter::Debug);
+        assert_eq!(level(&["-vv", "--debug"]), log::LevelFilter::Trace);
+    }
+
+    #[test]
+    fn save_artifacts_writes_input_and_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = Output {
+            stdout: b"out".to_vec(),
+            stderr: b"err".to_vec(),
+            printed: 6,
+        };
+        save_artifacts(dir.path(), "crash", Some(b"int x;"), &output).unwrap();
+        let read = |ext: &str| fs::read(dir.path().join(format!("crash.{ext}"))).unwrap();
+        assert_eq!(read("out"), b"int x;");
+        assert_eq!(read("stdout"), b"out");
+        assert_eq!(read("stderr"), b"err");
+
+        save_artifacts(dir.path(), "reduced", None, &output).unwrap();
+        assert!(!dir.path().join("reduced.out").exists());
+        assert!(dir.path().join("reduced.stderr").exists());
+
+        let missing = dir.path().join("missing");
+        assert!(save_artifacts(&missing, "crash", Some(b""), &output).is_err());
+    }
+
+    #[test]
+    fn buckets() {
+        let exited = Some(ExitStatus::from_raw(1 << 8));
+        let killed = Some(ExitStatus::from_raw(libc::SIGSEGV));
+        assert_eq!(
+            bucket(Outcome::Interesting, exited, 0xab),
+            Path::new("exit-1/00000000000000ab")
+        );
+        assert_eq!(
+            bucket(Outcome::Signal(libc::SIGSEGV), killed, 0xab),
+            Path::new("signal-11/00000000000000ab")
+        );
+        assert_eq!(
+            bucket(Outcome::Interesting, None, 1),
+            Path::new("other/0000000000000001")
+        );
+    }
+
+    #[test]
+    fn matches_each_part_of_the_check() {
+        let chk = sh("echo out; echo err >&2; exit 3", &[])
+            .with_output_regexes(Some(regex::Regex::new("out\\nerr").unwrap()), None);
+        let (status, output) = chk.run(b"").unwrap();
+        let matches = chk.matches(&status.unwrap(), &output);
+        assert!(!matches.exit_code);
+        assert!(matches.interesting_stdout.is_none());
+        assert_eq!(matches.interesting_output.unwrap().text, "out\nerr");
+        assert_eq!(chk.classify(status, &output), Outcome::Interesting);
+    }
+
+    #[test]
+    fn unused_files_over_the_run() {
+        let mut totals = JobStats::default();
+        for uses in [[("a.c", 1), ("b.c", 2)], [("a.c", 1), ("c.c", 5)]] {
+            totals += JobStats {
+                uses: uses.iter().map(|(k, n)| (k.to_string(), *n)).collect(),
+                ..JobStats::default()
+            };
+        }
+        let keys = ["a.c", "b.c", "c.c", "d.c"];
+        assert_eq!(
+            unused_files(keys.into_iter(), &totals.uses, 3),
+            [("a.c", 2), ("b.c", 2), ("d.c", 0)]
+        );
+        assert!(unused_files(keys.into_iter(), &totals.uses, 0).is_empty());
+    }
+
+    #[test]
+    fn confirm_catches_flaky_crashes() {
+        let dir = tempfile::tempdir().unwrap();
+        let ran = dir.path().join("ran").to_string_lossy().into_owned();
+        // Only crashes the first time
+        let chk = sh("test -e \"$1\" && exit 0; touch \"$1\"; exit 1", &[&ran]);
+        assert!(confirm(&chk, b"").1);
+        assert!(!confirm(&chk, b"").1);
+    }
+
+    #[test]
+    fn confirm_uses_reduce_timeout() {
+        let args = args(&["--reduce-timeout", "100", "corpus", "true"]);
+        let chk = reduce_check(&sh("exec sleep 60", &[]), &args);
+        assert_eq!(chk.timeout(), Some(Duration::from_millis(100)));
+        let (rerun, crashed) = confirm(&chk, b"");
+        assert!(rerun.unwrap().0.is_none());
+        assert!(!crashed);
+    }
 }
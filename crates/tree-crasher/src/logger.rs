@@ -0,0 +1,105 @@
+//! Logging to stderr and, with `--log-file`, to a file.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use anyhow::{Context, Result};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+
+#[derive(Debug)]
+struct Logger {
+    level: LevelFilter,
+    file: Option<Mutex<File>>,
+    start: Instant,
+}
+
+fn prefix(level: Level) -> &'static str {
+    match level {
+        Level::Error => "[ERROR] ",
+        Level::Warn => "[WARN] ",
+        Level::Info => "",
+        Level::Debug => "[DEBUG] ",
+        Level::Trace => "[TRACE] ",
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!("{}{}", prefix(record.level()), record.args());
+        eprintln!("{line}");
+        if let Some(file) = &self.file {
+            // There's nowhere to report a failure to log
+            let secs = self.start.elapsed().as_secs_f64();
+            writeln!(file.lock().unwrap(), "[{secs:>10.3}s] {line}").ok();
+        }
+    }
+
+    fn flush(&self) {
+        if let Some(file) = &self.file {
+            file.lock().unwrap().flush().ok();
+        }
+    }
+}
+
+/// Log messages up to `level` to stderr, and append them to `file` if given
+pub(crate) fn init(level: LevelFilter, file: Option<&Path>) -> Result<()> {
+    let file = match file {
+        Some(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?,
+        ),
+        None => None,
+    };
+    log::set_boxed_logger(Box::new(Logger {
+        level,
+        file: file.map(Mutex::new),
+        start: Instant::now(),
+    }))
+    .context("A logger was already set up")?;
+    log::set_max_level(level);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn level_filters_records() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("log");
+        let logger = Logger {
+            level: LevelFilter::Info,
+            file: Some(Mutex::new(File::create(&path).unwrap())),
+            start: Instant::now(),
+        };
+        for (level, message) in [(Level::Debug, "hidden"), (Level::Info, "shown")] {
+            logger.log(
+                &Record::builder()
+                    .level(level)
+                    .args(format_args!("{message}"))
+                    .build(),
+            );
+        }
+        logger.flush();
+        let text = fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().count(), 1);
+        assert!(text.ends_with("s] shown\n"), "{text}");
+        assert!(!logger.enabled(&Metadata::builder().level(Level::Debug).build()));
+    }
+}
@@ -0,0 +1,254 @@
+//! Describing how an artifact was found, so that it can be reproduced.
+
+use std::fs;
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use log::warn;
+use uuid::Uuid;
+
+use crate::check::CmdCheck;
+use crate::splice::SubSeeds;
+use crate::{read_file, Args};
+
+/// Where a test came from
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Source<'a> {
+    /// Key of the corpus file that was mutated
+    pub(crate) file: &'a str,
+    /// Seed of the splicer, if one was used
+    pub(crate) splice_seed: Option<u64>,
+    /// Seed of Radamsa, if it was used
+    pub(crate) radamsa_seed: Option<u32>,
+    /// The seeds that `file` was promoted from by feedback, nearest first
+    ///
+    /// Splices also take nodes from other files in the corpus, but tree-splicer
+    /// doesn't say which.
+    pub(crate) ancestors: &'a [String],
+}
+
+/// Contents of the `.json` file saved next to each artifact
+#[derive(Debug, serde::Serialize)]
+pub(crate) struct Metadata<'a> {
+    version: &'static str,
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+    /// What was run, including any --wrapper
+    command: Vec<String>,
+    timeout_ms: u64,
+    exit_code: Option<i32>,
+    signal: Option<i32>,
+    timed_out: bool,
+    seed: u64,
+    source_file: &'a str,
+    splice_seed: Option<u64>,
+    /// Derived from `splice_seed`, see [`SubSeeds`]
+    sub_seeds: Option<SubSeeds>,
+    radamsa_seed: Option<u32>,
+    ancestors: &'a [String],
+    /// See [`crate::signature::signature`]
+    signature: Option<u64>,
+    /// In bytes
+    size: usize,
+    reduced_size: Option<usize>,
+    /// How much smaller the reduced input is
+    reduction_percent: Option<f64>,
+    /// Which reduction settings worked, 0 for aggressive and 1 for conservative
+    reduce_attempt: Option<usize>,
+    /// Whether the reduced input still crashed when run again, if there is one
+    reduced_reproduces: Option<bool>,
+}
+
+impl<'a> Metadata<'a> {
+    /// `status` is `None` if the target timed out.
+    pub(crate) fn new(
+        args: &'a Args,
+        chk: &CmdCheck,
+        status: Option<ExitStatus>,
+        source: Source<'a>,
+    ) -> Self {
+        Metadata {
+            version: env!("CARGO_PKG_VERSION"),
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            command: chk.argv(),
+            timeout_ms: args.timeout,
+            exit_code: status.and_then(|s| s.code()),
+            signal: status.and_then(|s| s.signal()),
+            timed_out: status.is_none(),
+            seed: args.seed,
+            source_file: source.file,
+            splice_seed: source.splice_seed,
+            sub_seeds: source.splice_seed.map(SubSeeds::new),
+            radamsa_seed: source.radamsa_seed,
+            ancestors: source.ancestors,
+            signature: None,
+            size: 0,
+            reduced_size: None,
+            reduction_percent: None,
+            reduce_attempt: None,
+            reduced_reproduces: None,
+        }
+    }
+
+    pub(crate) fn with_signature(mut self, sig: Option<u64>) -> Self {
+        self.signature = sig;
+        self
+    }
+
+    /// Sizes of the input before and after reduction, if it was reduced
+    pub(crate) fn with_sizes(mut self, size: usize, reduced_size: Option<usize>) -> Self {
+        self.size = size;
+        self.reduced_size = reduced_size;
+        self.reduction_percent = reduced_size.map(|r| crate::percent_smaller(size, r));
+        self
+    }
+
+    pub(crate) fn with_reduce_attempt(mut self, attempt: Option<usize>) -> Self {
+        self.reduce_attempt = attempt;
+        self
+    }
+
+    pub(crate) fn with_reduced_reproduces(mut self, reproduces: Option<bool>) -> Self {
+        self.reduced_reproduces = reproduces;
+        self
+    }
+
+    pub(crate) fn save(&self, dir: &Path, stem: &str) -> Result<()> {
+        let path = dir.join(format!("{stem}.json"));
+        let json = serde_json::to_vec_pretty(self).context("Failed to serialize metadata")?;
+        fs::write(&path, json).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// The part of a saved `.json` file needed to resume a campaign
+#[derive(Debug, serde::Deserialize)]
+struct Saved {
+    signature: Option<u64>,
+}
+
+/// What was found in an output directory by an earlier run
+#[derive(Debug, Default)]
+pub(crate) struct Resumed {
+    pub(crate) signatures: Vec<u64>,
+    /// Highest number of an artifact named by [`crate::NameScheme::Sequential`]
+    pub(crate) last_artifact: u64,
+}
+
+/// Collect the signatures and artifact numbers of the artifacts in `dir`,
+/// including those in per-worker and --layout subdirectories
+pub(crate) fn scan(dir: &Path, resumed: &mut Resumed) -> Result<()> {
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Failed to read directory {}", dir.display()))?
+    {
+        let path = entry?.path();
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.is_dir() {
+            scan(&path, resumed)?;
+            continue;
+        }
+        let Some(stem) = name.strip_suffix(".json") else {
+            continue;
+        };
+        // UUIDs can end in digits, too
+        let uuid = stem.len() >= 36 && Uuid::parse_str(&stem[stem.len() - 36..]).is_ok();
+        if let Some((_, n)) = stem.rsplit_once('-').filter(|_| !uuid) {
+            if let Ok(n) = n.parse::<u64>() {
+                resumed.last_artifact = resumed.last_artifact.max(n);
+            }
+        }
+        let saved: Saved = match serde_json::from_slice(&read_file(&path)?) {
+            Ok(saved) => saved,
+            Err(e) => {
+                warn!("Failed to parse {}: {e}", path.display());
+                continue;
+            }
+        };
+        resumed.signatures.extend(saved.signature);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+    use crate::testing::args;
+
+    #[test]
+    fn json_round_trip() {
+        let args = args(&["--seed", "7", "--wrapper", "env", "corpus", "true"]);
+        let chk = crate::check_from_args(&args, args.check.clone()).unwrap();
+        let source = Source {
+            file: "seed.c",
+            splice_seed: Some(3),
+            radamsa_seed: None,
+            ancestors: &[],
+        };
+        let dir = tempfile::tempdir().unwrap();
+        Metadata::new(
+            &args,
+            &chk,
+            Some(ExitStatus::from_raw(libc::SIGSEGV)),
+            source,
+        )
+        .with_signature(Some(42))
+        .with_sizes(10, Some(5))
+        .with_reduce_attempt(Some(1))
+        .save(dir.path(), "crash")
+        .unwrap();
+        let json: Value =
+            serde_json::from_slice(&fs::read(dir.path().join("crash.json")).unwrap()).unwrap();
+        assert_eq!(json["command"], json!(["env", "true"]));
+        assert_eq!(json["signal"], libc::SIGSEGV);
+        assert!(json["exit_code"].is_null());
+        assert_eq!(json["timed_out"], false);
+        assert_eq!(json["seed"], 7);
+        assert_eq!(json["source_file"], "seed.c");
+        assert_eq!(json["splice_seed"], 3);
+        let sub_seeds = SubSeeds::new(3);
+        assert_eq!(json["sub_seeds"]["splice"], sub_seeds.splice);
+        assert_eq!(json["sub_seeds"]["deletion"], sub_seeds.deletion);
+        assert_eq!(json["sub_seeds"]["chaos"], sub_seeds.chaos);
+        assert_eq!(json["signature"], 42);
+        assert_eq!(json["size"], 10);
+        assert_eq!(json["reduced_size"], 5);
+        assert_eq!(json["reduction_percent"], 50.0);
+        assert_eq!(json["reduce_attempt"], 1);
+    }
+
+    #[test]
+    fn scan_reads_back_signatures() {
+        let args = args(&["corpus", "true"]);
+        let chk = crate::check_from_args(&args, args.check.clone()).unwrap();
+        let source = Source {
+            file: "seed.c",
+            splice_seed: None,
+            radamsa_seed: None,
+            ancestors: &[],
+        };
+        let dir = tempfile::tempdir().unwrap();
+        let worker = dir.path().join("worker-1");
+        fs::create_dir(&worker).unwrap();
+        for (dir, stem, sig) in [
+            (dir.path(), "crash-x-000002", 1),
+            (worker.as_path(), "crash-x-000007", 2),
+        ] {
+            Metadata::new(&args, &chk, None, source)
+                .with_signature(Some(sig))
+                .save(dir, stem)
+                .unwrap();
+        }
+        fs::write(dir.path().join("broken.json"), "{").unwrap();
+        let mut resumed = Resumed::default();
+        scan(dir.path(), &mut resumed).unwrap();
+        resumed.signatures.sort_unstable();
+        assert_eq!(resumed.signatures, [1, 2]);
+        assert_eq!(resumed.last_artifact, 7);
+    }
+}
@@ -0,0 +1,164 @@
+//! Serving the fuzzing counters to Prometheus over HTTP, with `--metrics-addr`.
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use log::warn;
+
+use crate::Progress;
+
+/// The counters in the Prometheus text format
+fn render(progress: &Progress) -> String {
+    let uptime = progress.start.elapsed().as_secs_f64();
+    let execs = progress.execs.load(Ordering::Relaxed);
+    let metrics = [
+        ("execs_total", "counter", "Tests run", execs as f64),
+        (
+            "execs_per_second",
+            "gauge",
+            "Tests run per second, on average",
+            execs as f64 / uptime.max(f64::EPSILON),
+        ),
+        (
+            "crashes_total",
+            "counter",
+            "Crashes saved",
+            progress.crashes.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "hangs_total",
+            "counter",
+            "Tests that timed out",
+            progress.hangs.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "ooms_total",
+            "counter",
+            "Tests where the target was killed with SIGKILL",
+            progress.ooms.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "uptime_seconds",
+            "gauge",
+            "Time since fuzzing started",
+            uptime,
+        ),
+    ];
+    let mut text = String::new();
+    for (name, kind, help, value) in metrics {
+        writeln!(text, "# HELP tree_crasher_{name} {help}").unwrap();
+        writeln!(text, "# TYPE tree_crasher_{name} {kind}").unwrap();
+        writeln!(text, "tree_crasher_{name} {value}").unwrap();
+    }
+    text
+}
+
+fn respond(stream: TcpStream, progress: &Progress) -> io::Result<()> {
+    // Blocking, but don't let a slow client hold up the others for long
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+    let mut request = String::new();
+    BufReader::new(&stream).read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or_default();
+    let (status, body) = match path {
+        "/metrics" => ("200 OK", render(progress)),
+        _ => ("404 Not Found", String::from("Try /metrics\n")),
+    };
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Answer requests for `/metrics` until `done` is set
+pub(crate) fn serve(listener: &TcpListener, progress: &Progress, done: &AtomicBool) {
+    if let Err(e) = listener.set_nonblocking(true) {
+        warn!("Not serving metrics: {e}");
+        return;
+    }
+    while !done.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                if let Err(e) = respond(stream, progress) {
+                    warn!("Failed to serve metrics: {e}");
+                }
+            }
+            // Poll in short increments so as not to hold up exiting
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                std::thread::sleep(Duration::from_millis(100));
+            }
+            Err(e) => warn!("Failed to accept metrics connection: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Read;
+    use std::sync::Arc;
+
+    use super::*;
+
+    fn progress() -> Progress {
+        let progress = Progress::new(Arc::new(AtomicBool::new(false)));
+        progress.execs.store(10, Ordering::Relaxed);
+        progress.crashes.store(2, Ordering::Relaxed);
+        progress.hangs.store(1, Ordering::Relaxed);
+        progress
+    }
+
+    /// The value of each sample in the text format
+    fn samples(text: &str) -> HashMap<&str, f64> {
+        text.lines()
+            .filter(|l| !l.starts_with('#'))
+            .map(|l| {
+                let (name, value) = l.split_once(' ').unwrap();
+                (name, value.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn counters() {
+        let text = render(&progress());
+        let samples = samples(&text);
+        assert_eq!(samples["tree_crasher_execs_total"], 10.0);
+        assert_eq!(samples["tree_crasher_crashes_total"], 2.0);
+        assert_eq!(samples["tree_crasher_hangs_total"], 1.0);
+        assert_eq!(samples["tree_crasher_ooms_total"], 0.0);
+        assert!(text.contains("# TYPE tree_crasher_execs_total counter\n"));
+    }
+
+    fn get(addr: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        // In one write, so that the server reads all of it
+        let request = format!("GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_metrics() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (progress, done) = (progress(), AtomicBool::new(false));
+        let (metrics, other) = std::thread::scope(|s| {
+            s.spawn(|| serve(&listener, &progress, &done));
+            let responses = (get(addr, "/metrics"), get(addr, "/"));
+            done.store(true, Ordering::Relaxed);
+            responses
+        });
+        let (head, body) = metrics.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+        assert_eq!(samples(body)["tree_crasher_execs_total"], 10.0);
+        assert!(other.starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
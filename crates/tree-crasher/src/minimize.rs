@@ -0,0 +1,78 @@
+//! Shrinking the seed corpus before a campaign.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::{Context, Result};
+use log::info;
+use tree_sitter::{Language, Tree};
+
+use crate::{corpus, nodes, Args};
+
+/// The kinds of all nodes in `tree`
+fn node_kinds(tree: &Tree) -> HashSet<u16> {
+    nodes(tree).iter().map(|n| n.kind_id()).collect()
+}
+
+/// Copy a subset of the corpus in `files`, i.e., `paths`, that covers the same
+/// node kinds to `output`, returning the size of the corpus
+///
+/// Files are considered from most to fewest node kinds (and then from smallest
+/// to largest), and kept only if they contain a kind that no kept file does.
+pub(crate) fn minimize(
+    language: Language,
+    args: &Args,
+    files: &Path,
+    paths: &[PathBuf],
+    output: &Path,
+    route: &dyn Fn(&Path) -> bool,
+) -> Result<usize> {
+    let corpus = corpus::load(language, paths, args, route)?;
+    if corpus.is_empty() {
+        return Ok(0);
+    }
+    let mut candidates: Vec<_> = corpus
+        .iter()
+        .map(|(path, (bytes, tree))| (path, bytes, node_kinds(tree)))
+        .collect();
+    candidates.sort_by(|(p1, b1, k1), (p2, b2, k2)| {
+        k2.len()
+            .cmp(&k1.len())
+            .then(b1.len().cmp(&b2.len()))
+            .then(p1.cmp(p2))
+    });
+
+    let mut covered = HashSet::new();
+    let mut dropped = 0;
+    let mut dropped_bytes = 0;
+    for (path, bytes, kinds) in candidates {
+        if kinds.is_subset(&covered) {
+            dropped += 1;
+            dropped_bytes += bytes.len();
+            continue;
+        }
+        covered.extend(kinds);
+        // Files listed on stdin may be anywhere, but must end up in `output`
+        let relative: PathBuf = Path::new(path)
+            .strip_prefix(files)
+            .unwrap_or(Path::new(path))
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        let dest = output.join(relative);
+        if let Some(parent) = dest.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory {}", parent.display()))?;
+        }
+        // Not `bytes`, which may have been decompressed
+        fs::copy(path, &dest)
+            .with_context(|| format!("Failed to copy {path} to {}", dest.display()))?;
+    }
+    info!(
+        "Kept {} of {} corpus files, eliminated {dropped} files ({dropped_bytes} bytes)",
+        corpus.len() - dropped,
+        corpus.len()
+    );
+    Ok(corpus.len())
+}
@@ -0,0 +1,322 @@
+//! Ways of producing new test inputs from the corpus.
+
+use std::collections::HashMap;
+
+use rand::distributions::WeightedIndex;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use tree_sitter::{Language, Tree};
+use tree_splicer::node_types::NodeTypes;
+use tree_splicer::splice::Config;
+
+use crate::metadata::Source;
+use crate::splice::Splicer;
+use crate::{corpus, dict, Args, CorpusOrder};
+
+/// A mutation engine, set up for one batch of tests
+pub(crate) trait Mutator<'a> {
+    /// Produce a mutant and say where it came from, or `None` if this engine
+    /// has nothing more to offer for this batch
+    fn next(&mut self, rng: &mut StdRng) -> Option<(Vec<u8>, Source<'a>)>;
+}
+
+/// The engine selected by `args`
+pub(crate) fn new<'a>(
+    language: Language,
+    node_types: &NodeTypes,
+    args: &Args,
+    corpus: &'a HashMap<String, (Vec<u8>, Tree)>,
+    weights: &[(glob::Pattern, u32)],
+    dict: &'a [Vec<u8>],
+    rng: &mut StdRng,
+) -> Box<dyn Mutator<'a> + 'a> {
+    let seeds = Seeds::new(corpus, weights, args);
+    #[cfg(feature = "radamsa")]
+    if args.radamsa {
+        return Box::new(RadamsaMutator::new(corpus, seeds, args.max_size));
+    }
+    Box::new(SpliceMutator::new(
+        language, node_types, args, corpus, seeds, dict, rng,
+    ))
+}
+
+/// The corpus files no bigger than `--max-size`, in `--corpus-order`, to be
+/// picked according to `--weights`
+///
+/// The files are picked here rather than in the engines, so that we know where
+/// each mutant came from.
+struct Seeds<'a> {
+    keys: Vec<&'a str>,
+    /// `None` if there's nothing to pick
+    index: Option<WeightedIndex<u32>>,
+}
+
+impl<'a> Seeds<'a> {
+    fn new(
+        corpus: &'a HashMap<String, (Vec<u8>, Tree)>,
+        weights: &[(glob::Pattern, u32)],
+        args: &Args,
+    ) -> Self {
+        let mut keys: Vec<_> = corpus
+            .iter()
+            .filter(|(_, (text, _))| text.len() <= args.max_size)
+            .map(|(key, _)| key.as_str())
+            .collect();
+        match args.corpus_order {
+            CorpusOrder::Hash => (),
+            CorpusOrder::Sorted => keys.sort_unstable(),
+            CorpusOrder::Shuffled => {
+                keys.sort_unstable();
+                // Not the thread's RNG, so that all threads agree
+                keys.shuffle(&mut StdRng::seed_from_u64(args.seed));
+            }
+        }
+        let index = WeightedIndex::new(keys.iter().map(|k| corpus::weight(weights, k))).ok();
+        Seeds { keys, index }
+    }
+
+    fn pick(&self, rng: &mut StdRng) -> Option<&'a str> {
+        let index = self.index.as_ref()?;
+        Some(self.keys[rng.sample(index)])
+    }
+}
+
+/// Grammar-aware splicing with tree-splicer
+pub(crate) struct SpliceMutator<'a> {
+    language: Language,
+    corpus: &'a HashMap<String, (Vec<u8>, Tree)>,
+    seeds: Seeds<'a>,
+    splicer: Splicer<'a>,
+    seed: u64,
+    dict: &'a [Vec<u8>],
+    max_size: usize,
+}
+
+/// Settings for tree-splicer
+///
+/// Settings not given in `args` (or all, with --randomize-mutations) are
+/// chosen at random. tree-splicer 0.5 doesn't splice within a file, so there's
+/// no setting for that.
+fn config(language: Language, node_types: &NodeTypes, args: &Args, rng: &mut StdRng) -> Config {
+    const INTER_SPLICES_RANGE: std::ops::Range<usize> = 12..48;
+    const CHAOS_RANGE: std::ops::Range<u8> = 15..20;
+    const DELETIONS_RANGE: std::ops::Range<u8> = 10..20;
+
+    let random_inter_splices = rng.gen_range(INTER_SPLICES_RANGE);
+    let random_seed = rng.gen::<u64>();
+    let random_chaos_range = rng.gen_range(CHAOS_RANGE);
+    let random_deletions_range = rng.gen_range(DELETIONS_RANGE);
+    let (chaos, deletions, mutations) = if args.randomize_mutations {
+        (None, None, None)
+    } else {
+        (args.chaos, args.deletions, args.mutations)
+    };
+
+    Config {
+        chaos: chaos.unwrap_or(random_chaos_range),
+        deletions: deletions.unwrap_or(random_deletions_range),
+        language,
+        // intra_splices: 10,
+        inter_splices: mutations.unwrap_or(random_inter_splices),
+        node_types: node_types.clone(),
+        max_size: args.max_size,
+        reparse: args.reparse.unwrap_or(usize::MAX),
+        seed: random_seed, //args.seed,
+    }
+}
+
+impl<'a> SpliceMutator<'a> {
+    fn new(
+        language: Language,
+        node_types: &NodeTypes,
+        args: &Args,
+        corpus: &'a HashMap<String, (Vec<u8>, Tree)>,
+        seeds: Seeds<'a>,
+        dict: &'a [Vec<u8>],
+        rng: &mut StdRng,
+    ) -> Self {
+        let config = config(language, node_types, args, rng);
+        let seed = config.seed;
+        SpliceMutator {
+            language,
+            corpus,
+            seeds,
+            splicer: Splicer::new(config, corpus),
+            seed,
+            dict,
+            max_size: args.max_size,
+        }
+    }
+}
+
+impl<'a> Mutator<'a> for SpliceMutator<'a> {
+    fn next(&mut self, rng: &mut StdRng) -> Option<(Vec<u8>, Source<'a>)> {
+        let key = self.seeds.pick(rng)?;
+        let (text, tree) = &self.corpus[key];
+        let out = self.splicer.splice_tree(text, tree.clone())?;
+        let out = dict::maybe_inject(self.language, rng, self.dict, out, self.max_size);
+        let source = Source {
+            file: key,
+            splice_seed: Some(self.seed),
+            radamsa_seed: None,
+            ancestors: &[],
+        };
+        Some((out, source))
+    }
+}
+
+#[cfg(feature = "radamsa")]
+thread_local! {
+    /// Radamsa's input and output, reused across batches since --max-size
+    /// and corpus files may be large
+    static RADAMSA_BUFS: std::cell::RefCell<(Vec<u8>, Vec<u8>)> =
+        const { std::cell::RefCell::new((Vec::new(), Vec::new())) };
+}
+
+/// Grammar-agnostic mutations with Radamsa
+#[cfg(feature = "radamsa")]
+pub(crate) struct RadamsaMutator<'a> {
+    corpus: &'a HashMap<String, (Vec<u8>, Tree)>,
+    seeds: Seeds<'a>,
+    max_size: usize,
+}
+
+#[cfg(feature = "radamsa")]
+impl<'a> RadamsaMutator<'a> {
+    fn new(
+        corpus: &'a HashMap<String, (Vec<u8>, Tree)>,
+        seeds: Seeds<'a>,
+        max_size: usize,
+    ) -> Self {
+        static INIT: std::sync::Once = std::sync::Once::new();
+        INIT.call_once(|| unsafe { radamsa_sys::radamsa_init() });
+        RadamsaMutator {
+            corpus,
+            seeds,
+            max_size,
+        }
+    }
+}
+
+#[cfg(feature = "radamsa")]
+impl<'a> Mutator<'a> for RadamsaMutator<'a> {
+    fn next(&mut self, rng: &mut StdRng) -> Option<(Vec<u8>, Source<'a>)> {
+        let key = self.seeds.pick(rng)?;
+        let seed = rng.gen::<u32>();
+        let out = RADAMSA_BUFS.with_borrow_mut(|(input, buf)| {
+            // Radamsa takes a mutable pointer, so it gets a copy
+            input.clear();
+            input.extend_from_slice(&self.corpus[key].0);
+            buf.resize(self.max_size, 0);
+            let out_len = unsafe {
+                radamsa_sys::radamsa(
+                    input.as_mut_ptr(),
+                    input.len(),
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    seed,
+                )
+            };
+            assert!(out_len <= buf.len());
+            buf[..out_len].to_vec()
+        });
+        let source = Source {
+            file: key,
+            splice_seed: None,
+            radamsa_seed: Some(seed),
+            ancestors: &[],
+        };
+        Some((out, source))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::args;
+
+    /// The same files each time, but stored in a different order
+    fn corpus() -> HashMap<String, (Vec<u8>, Tree)> {
+        (0..16)
+            .map(|i| {
+                let text = format!("int x{i};").into_bytes();
+                let tree = crate::parse(tree_sitter_c::language(), &text).unwrap();
+                (format!("{i}.c"), (text, tree))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_picks() {
+        for order in ["sorted", "shuffled"] {
+            let args = args(&["--corpus-order", order, "--seed", "42", "corpus", "true"]);
+            let picks = |corpus: &HashMap<_, _>| {
+                let seeds = Seeds::new(corpus, &[], &args);
+                let mut rng = StdRng::seed_from_u64(args.seed);
+                (0..32)
+                    .map(|_| seeds.pick(&mut rng).unwrap().to_string())
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(picks(&corpus()), picks(&corpus()));
+        }
+    }
+
+    #[test]
+    fn splice_mutants() {
+        let corpus = corpus();
+        let node_types = NodeTypes::new(tree_sitter_c::NODE_TYPES).unwrap();
+        let args = args(&["corpus", "true"]);
+        let mut rng = StdRng::seed_from_u64(0);
+        let language = tree_sitter_c::language();
+        let mut mutator = new(language, &node_types, &args, &corpus, &[], &[], &mut rng);
+        let mutants: Vec<_> = (0..32).map_while(|_| mutator.next(&mut rng)).collect();
+        assert!(!mutants.is_empty());
+        assert!(mutants
+            .iter()
+            .all(|(_, source)| corpus.contains_key(source.file)));
+        assert!(mutants.iter().any(|(mutant, _)| {
+            crate::parse(language, mutant).is_ok_and(|t| !t.root_node().has_error())
+        }));
+    }
+
+    #[test]
+    fn config_from_args() {
+        let node_types = NodeTypes::new(tree_sitter_c::NODE_TYPES).unwrap();
+        let build = |flags: &[&str]| {
+            let argv: Vec<_> = flags.iter().copied().chain(["corpus", "true"]).collect();
+            let mut rng = StdRng::seed_from_u64(0);
+            config(
+                tree_sitter_c::language(),
+                &node_types,
+                &args(&argv),
+                &mut rng,
+            )
+        };
+        let flags = [
+            "--chaos",
+            "3",
+            "--deletions",
+            "4",
+            "--mutations",
+            "5",
+            "--reparse",
+            "6",
+            "--max-size",
+            "7",
+        ];
+        let c = build(&flags);
+        assert_eq!(
+            (c.chaos, c.deletions, c.inter_splices, c.reparse, c.max_size),
+            (3, 4, 5, 6, 7)
+        );
+        assert_eq!(build(&[]).reparse, usize::MAX);
+        let mut randomized = flags.to_vec();
+        randomized.push("--randomize-mutations");
+        let c = build(&randomized);
+        assert!((15..20).contains(&c.chaos));
+        assert!((10..20).contains(&c.deletions));
+        assert!((12..48).contains(&c.inter_splices));
+        assert_eq!((c.reparse, c.max_size), (6, 7));
+    }
+}
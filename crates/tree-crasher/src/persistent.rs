@@ -0,0 +1,287 @@
+//! Running many inputs through one long-lived target process.
+//!
+//! With `--persistent`, the target reads inputs from stdin, each one preceded
+//! by its length as a 4-byte little-endian integer. After handling an input,
+//! it writes a single byte to stdout: zero if the input was boring, anything
+//! else if it was interesting. Crashing (or exiting) instead of replying
+//! counts as having run the input normally, i.e., it's judged by the exit code
+//! or signal. The target should exit when stdin is closed, and must not write
+//! anything else to stdout.
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::process::{Child, ChildStdin, ChildStdout};
+use std::time::Duration;
+
+use log::warn;
+
+use crate::check::{CmdCheck, Outcome, Output};
+
+/// The length prefix for sending `input` to a persistent target
+pub(crate) fn frame_header(input: &[u8]) -> io::Result<[u8; 4]> {
+    let len = u32::try_from(input.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "input too large"))?;
+    Ok(len.to_le_bytes())
+}
+
+/// Wait up to `timeout` for `pipe` to become readable, returning whether it
+/// did
+pub(crate) fn poll(pipe: &impl AsRawFd, timeout: Option<Duration>) -> io::Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: pipe.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    let ms = timeout.map_or(-1, |t| {
+        libc::c_int::try_from(t.as_millis()).unwrap_or(libc::c_int::MAX)
+    });
+    loop {
+        // SAFETY: `fd` is a valid pollfd and the count is 1.
+        match unsafe { libc::poll(&mut fd, 1, ms) } {
+            -1 => {
+                let e = io::Error::last_os_error();
+                if e.kind() != io::ErrorKind::Interrupted {
+                    return Err(e);
+                }
+            }
+            n => return Ok(n > 0),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: ChildStdout,
+    runs: usize,
+}
+
+/// A long-running target that runs many inputs
+pub(crate) trait Server {
+    /// Run the target on `input`
+    ///
+    /// Interesting inputs should be confirmed with a fresh process, both to
+    /// get the target's output and because the target's state may have
+    /// contributed.
+    fn run(&mut self, input: &[u8]) -> io::Result<Outcome>;
+
+    /// Whether the last run reached code that no earlier run did
+    fn new_coverage(&self) -> bool {
+        false
+    }
+}
+
+/// A persistent target, restarted after it crashes, hangs, or has handled
+/// `max_runs` inputs
+#[derive(Debug)]
+pub(crate) struct Persistent<'a> {
+    chk: &'a CmdCheck,
+    max_runs: usize,
+    process: Option<Process>,
+}
+
+impl<'a> Persistent<'a> {
+    pub(crate) fn new(chk: &'a CmdCheck, max_runs: usize) -> Self {
+        Persistent {
+            chk,
+            max_runs,
+            process: None,
+        }
+    }
+
+    fn spawn(&self) -> io::Result<Process> {
+        let mut child = self.chk.spawn_persistent()?;
+        let stdin = child.stdin.take().unwrap();
+        let stdout = child.stdout.take().unwrap();
+        Ok(Process {
+            child,
+            stdin,
+            stdout,
+            runs: 0,
+        })
+    }
+
+    /// Judge the target, which exited instead of replying
+    fn exited(&mut self) -> io::Result<Outcome> {
+        let mut process = self.process.take().unwrap();
+        let status = process.child.wait()?;
+        Ok(self.chk.classify(Some(status), &Output::default()))
+    }
+
+    /// Close the target's stdin so that it exits, or kill it
+    fn stop(&mut self, kill: bool) -> io::Result<()> {
+        let Some(mut process) = self.process.take() else {
+            return Ok(());
+        };
+        drop(process.stdin);
+        if kill || !poll(&process.stdout, self.chk.timeout())? {
+            process.child.kill()?;
+        }
+        process.child.wait()?;
+        Ok(())
+    }
+}
+
+impl Server for Persistent<'_> {
+    fn run(&mut self, input: &[u8]) -> io::Result<Outcome> {
+        if self.process.is_none() {
+            self.process = Some(self.spawn()?);
+        }
+        let process = self.process.as_mut().unwrap();
+        let header = frame_header(input)?;
+        let sent = process
+            .stdin
+            .write_all(&header)
+            .and_then(|()| process.stdin.write_all(input))
+            .and_then(|()| process.stdin.flush());
+        match sent {
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return self.exited(),
+            r => r?,
+        }
+        if !poll(&process.stdout, self.chk.timeout())? {
+            self.stop(true)?;
+            return Ok(Outcome::Timeout);
+        }
+        let mut reply = [0u8];
+        if process.stdout.read(&mut reply)? == 0 {
+            return self.exited();
+        }
+        process.runs += 1;
+        if process.runs >= self.max_runs {
+            self.stop(false)?;
+        }
+        Ok(if reply[0] == 0 {
+            Outcome::Boring
+        } else {
+            Outcome::Interesting
+        })
+    }
+}
+
+impl Drop for Persistent<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.stop(true) {
+            warn!("Failed to stop persistent target: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::testing::compile;
+
+    /// Notes each start in the file named by its argument, and finds inputs
+    /// that contain `!` interesting
+    const TARGET: &str = r#"
+#include <stdio.h>
+#include <string.h>
+
+int main(int argc, char **argv) {
+    FILE *starts = fopen(argv[1], "a");
+    fputs("started\n", starts);
+    fclose(starts);
+    static char buf[1 << 16];
+    unsigned char header[4];
+    while (fread(header, 1, 4, stdin) == 4) {
+        size_t len = header[0] | header[1] << 8 | header[2] << 16 | (size_t)header[3] << 24;
+        if (len > sizeof buf || fread(buf, 1, len, stdin) != len) {
+            return 2;
+        }
+        char reply = memchr(buf, '!', len) != NULL;
+        fwrite(&reply, 1, 1, stdout);
+        fflush(stdout);
+    }
+    return 0;
+}
+"#;
+
+    fn target(dir: &Path) -> CmdCheck {
+        let exe = compile(dir, "persistent", TARGET);
+        let starts = dir.join("starts").to_string_lossy().into_owned();
+        CmdCheck::new(
+            exe.to_string_lossy().into_owned(),
+            vec![starts],
+            vec![],
+            None,
+            None,
+            None,
+            None,
+            None,
+            false,
+            false,
+            Some(Duration::from_secs(10)),
+        )
+        .with_persistent(true)
+    }
+
+    fn starts(dir: &Path) -> usize {
+        fs::read_to_string(dir.join("starts"))
+            .unwrap()
+            .lines()
+            .count()
+    }
+
+    #[test]
+    fn frame_headers() {
+        assert_eq!(frame_header(b"").unwrap(), [0, 0, 0, 0]);
+        assert_eq!(frame_header(b"abc").unwrap(), [3, 0, 0, 0]);
+        assert_eq!(frame_header(&[0; 0x1234]).unwrap(), [0x34, 0x12, 0, 0]);
+    }
+
+    #[test]
+    fn reply_byte() {
+        let dir = tempfile::tempdir().unwrap();
+        let chk = target(dir.path());
+        let mut server = Persistent::new(&chk, 100);
+        assert_eq!(server.run(b"int x;").unwrap(), Outcome::Boring);
+        assert_eq!(server.run(b"int x!").unwrap(), Outcome::Interesting);
+        assert_eq!(server.run(b"").unwrap(), Outcome::Boring);
+        assert_eq!(starts(dir.path()), 1);
+        // A single input, as when confirming a crash
+        let (status, output) = chk.run(b"int x!").unwrap();
+        assert_eq!(chk.classify(status, &output), Outcome::Interesting);
+    }
+
+    #[test]
+    fn respawn_after_max_runs() {
+        let dir = tempfile::tempdir().unwrap();
+        let chk = target(dir.path());
+        let mut server = Persistent::new(&chk, 2);
+        for _ in 0..5 {
+            assert_eq!(server.run(b"int x;").unwrap(), Outcome::Boring);
+        }
+        assert_eq!(starts(dir.path()), 3);
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with `cargo test --release -- --ignored throughput`"]
+    fn throughput() {
+        const RUNS: u32 = 2000;
+        let dir = tempfile::tempdir().unwrap();
+        let chk = target(dir.path());
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            chk.run(b"int x;").unwrap();
+        }
+        let fresh = start.elapsed();
+        let mut server = Persistent::new(&chk, 10_000);
+        let start = Instant::now();
+        for _ in 0..RUNS {
+            server.run(b"int x;").unwrap();
+        }
+        let persistent = start.elapsed();
+        let per_sec = |d: Duration| f64::from(RUNS) / d.as_secs_f64();
+        eprintln!(
+            "{:.0} runs/s with a process per input, {:.0} runs/s with --persistent",
+            per_sec(fresh),
+            per_sec(persistent)
+        );
+        assert!(persistent * 3 < fresh);
+    }
+}
@@ -0,0 +1,137 @@
+//! Tracking which kinds of crashes have already been seen.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::OnceLock;
+
+use regex::bytes::Regex;
+use sha1_smol::Sha1;
+
+use crate::check::Outcome;
+use crate::Dedup;
+
+/// Strip parts of the target's output that differ between runs, e.g.,
+/// addresses and the PIDs in sanitizer reports
+fn normalize(stderr: &[u8]) -> Vec<u8> {
+    static ADDR: OnceLock<Regex> = OnceLock::new();
+    static PID: OnceLock<Regex> = OnceLock::new();
+    let addr = ADDR.get_or_init(|| Regex::new(r"0x[0-9a-fA-F]+").unwrap());
+    let pid = PID.get_or_init(|| Regex::new(r"==[0-9]+==").unwrap());
+    let stderr = addr.replace_all(stderr, &b"0x"[..]);
+    pid.replace_all(&stderr, &b"=="[..]).into_owned()
+}
+
+/// A coarse summary of a crash, used to spot repeats
+///
+/// Signatures are saved in metadata and read back by --resume, so they must
+/// not change between builds (as those of `DefaultHasher` may).
+pub(crate) fn signature(dedup: Dedup, outcome: Outcome, stderr: &[u8]) -> u64 {
+    let tag = match outcome {
+        Outcome::Boring => String::from("boring"),
+        Outcome::Timeout => String::from("timeout"),
+        Outcome::Interesting => String::from("interesting"),
+        Outcome::Oom => String::from("oom"),
+        Outcome::Signal(s) => format!("signal {s}"),
+    };
+    let mut hasher = Sha1::new();
+    hasher.update(tag.as_bytes());
+    hasher.update(b"\0");
+    match dedup {
+        Dedup::Signal => (),
+        Dedup::Off | Dedup::Stderr => hasher.update(&normalize(stderr)),
+    }
+    let digest = hasher.digest().bytes();
+    u64::from_le_bytes(digest[..8].try_into().unwrap())
+}
+
+/// Set of signatures that evicts the least-recently-seen when full
+///
+/// Evicted signatures are reported as new if they show up again, which is an
+/// acceptable price for bounded memory usage over very long runs.
+#[derive(Debug)]
+pub(crate) struct Signatures {
+    /// Zero means unbounded
+    max: usize,
+    tick: u64,
+    last_seen: HashMap<u64, u64>,
+    by_age: BTreeMap<u64, u64>,
+}
+
+impl Signatures {
+    pub(crate) fn new(max: usize) -> Self {
+        Signatures {
+            max,
+            tick: 0,
+            last_seen: HashMap::new(),
+            by_age: BTreeMap::new(),
+        }
+    }
+
+    /// Record a signature, returning whether it was new
+    pub(crate) fn insert(&mut self, sig: u64) -> bool {
+        self.tick += 1;
+        let new = match self.last_seen.insert(sig, self.tick) {
+            Some(old) => {
+                self.by_age.remove(&old);
+                false
+            }
+            None => true,
+        };
+        self.by_age.insert(self.tick, sig);
+        if self.max != 0 && self.last_seen.len() > self.max {
+            if let Some((_, oldest)) = self.by_age.pop_first() {
+                self.last_seen.remove(&oldest);
+            }
+        }
+        new
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_are_stable() {
+        let stderr = b"==123==ERROR: AddressSanitizer at 0xdeadbeef";
+        let sig = |dedup| signature(dedup, Outcome::Signal(11), stderr);
+        assert_eq!(sig(Dedup::Stderr), 0x8e54b6581d56d59b);
+        assert_eq!(sig(Dedup::Signal), 0xdd1108ec6c84f2cf);
+    }
+
+    #[test]
+    fn duplicates_are_suppressed() {
+        let first = b"==123==ERROR: AddressSanitizer: SEGV on unknown address 0x000000001234";
+        let second = b"==456==ERROR: AddressSanitizer: SEGV on unknown address 0x7ffe00005678";
+        let other = b"==456==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x1";
+        let sig = |stderr: &[u8]| signature(Dedup::Stderr, Outcome::Signal(11), stderr);
+        assert_eq!(sig(first), sig(second));
+        let mut seen = Signatures::new(0);
+        assert!(seen.insert(sig(first)));
+        assert!(!seen.insert(sig(second)));
+        assert!(seen.insert(sig(other)));
+        assert!(seen.insert(signature(Dedup::Stderr, Outcome::Signal(6), first)));
+    }
+
+    #[test]
+    fn oldest_signature_is_evicted() {
+        let mut seen = Signatures::new(2);
+        assert!(seen.insert(1));
+        assert!(seen.insert(2));
+        assert!(seen.insert(3));
+        // Evicted to make room for 3
+        assert!(seen.insert(1));
+        assert!(!seen.insert(3));
+    }
+
+    #[test]
+    fn seeing_a_signature_again_keeps_it() {
+        let mut seen = Signatures::new(2);
+        seen.insert(1);
+        seen.insert(2);
+        assert!(!seen.insert(1));
+        // Evicts 2, which was seen less recently than 1
+        seen.insert(3);
+        assert!(!seen.insert(1));
+        assert!(seen.insert(2));
+    }
+}
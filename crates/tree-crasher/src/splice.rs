@@ -0,0 +1,344 @@
+//! Splicing together corpus files.
+//!
+//! This is a fork of `tree_splicer::splice::Splicer` that makes the same
+//! choices given the same seed. tree-splicer collects nodes and kinds in hash
+//! maps and sets, whose iteration order differs between runs, so its mutants
+//! can't be reproduced from `--seed`. Here, files are visited in order of their
+//! paths and everything else in the order it was found.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tree_sitter::{Language, Node, Tree};
+use tree_sitter_edit::Editor;
+use tree_splicer::node_types::NodeTypes;
+use tree_splicer::splice::Config;
+
+#[derive(Debug, Default)]
+struct Edits(HashMap<usize, Vec<u8>>);
+
+impl Editor for Edits {
+    fn has_edit(&self, _tree: &Tree, node: &Node) -> bool {
+        self.0.contains_key(&node.id())
+    }
+
+    fn edit(&self, _source: &[u8], tree: &Tree, node: &Node) -> Vec<u8> {
+        debug_assert!(self.has_edit(tree, node));
+        self.0[&node.id()].clone()
+    }
+}
+
+/// The distinct texts of the nodes of each kind
+#[derive(Debug)]
+struct Branches<'a>(BTreeMap<&'static str, Vec<&'a [u8]>>);
+
+impl<'a> Branches<'a> {
+    fn new(trees: &[(&'a [u8], &'a Tree)]) -> Self {
+        let mut branches: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        let mut seen = HashSet::new();
+        for (text, tree) in trees {
+            for node in all_nodes(tree.root_node()) {
+                let branch = &text[node.byte_range()];
+                if seen.insert((node.kind(), branch)) {
+                    branches.entry(node.kind()).or_default().push(branch);
+                }
+            }
+        }
+        Branches(branches)
+    }
+}
+
+/// `root` and its descendants, breadth-first
+fn all_nodes(root: Node<'_>) -> Vec<Node<'_>> {
+    let mut all = vec![root];
+    let mut i = 0;
+    while let Some(node) = all.get(i).copied() {
+        let mut cursor = node.walk();
+        all.extend(node.children(&mut cursor));
+        i += 1;
+    }
+    all
+}
+
+/// A node other than the root, unless that's all there is
+fn pick_node<'b>(rng: &mut StdRng, tree: &'b Tree) -> Node<'b> {
+    let nodes = all_nodes(tree.root_node());
+    match nodes.len() {
+        1 => nodes[0],
+        n => nodes[rng.gen_range(1..n)],
+    }
+}
+
+fn parse(language: Language, code: &[u8]) -> Tree {
+    let mut parser = tree_sitter::Parser::new();
+    parser
+        .set_language(language)
+        .expect("Failed to set tree-sitter parser language");
+    parser.parse(code, None).expect("Failed to parse code")
+}
+
+/// Seeds for each kind of choice that the splicer makes, derived from its seed
+///
+/// Each seeds its own generator, so that, e.g., the chaotic edits can be
+/// replayed without the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize)]
+pub(crate) struct SubSeeds {
+    /// How many edits to make, and which nodes to splice in where
+    pub(crate) splice: u64,
+    /// Which edits are deletions, and which nodes they delete
+    pub(crate) deletion: u64,
+    /// Which edits are chaotic, and what they do instead
+    pub(crate) chaos: u64,
+}
+
+impl SubSeeds {
+    pub(crate) fn new(seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        SubSeeds {
+            splice: rng.gen(),
+            deletion: rng.gen(),
+            chaos: rng.gen(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Splicer<'a> {
+    language: Language,
+    branches: Branches<'a>,
+    chaos: u8,
+    deletions: u8,
+    kinds: Vec<&'static str>,
+    inter_splices: usize,
+    max_size: usize,
+    node_types: NodeTypes,
+    trees: Vec<(&'a [u8], &'a Tree)>,
+    reparse: usize,
+    splice_rng: StdRng,
+    deletion_rng: StdRng,
+    chaos_rng: StdRng,
+}
+
+impl<'a> Splicer<'a> {
+    fn delta(node: Node<'_>, replace: &[u8]) -> isize {
+        let range = node.byte_range();
+        isize::try_from(replace.len()).unwrap_or_default()
+            - isize::try_from(range.end - range.start).unwrap_or_default()
+    }
+
+    pub(crate) fn new(config: Config, files: &'a HashMap<String, (Vec<u8>, Tree)>) -> Self {
+        let seeds = SubSeeds::new(config.seed);
+        Self::with_sub_seeds(config, seeds, files)
+    }
+
+    /// Ignores the seed in `config`
+    pub(crate) fn with_sub_seeds(
+        config: Config,
+        seeds: SubSeeds,
+        files: &'a HashMap<String, (Vec<u8>, Tree)>,
+    ) -> Self {
+        let mut paths: Vec<_> = files.keys().collect();
+        paths.sort_unstable();
+        let trees: Vec<_> = paths
+            .into_iter()
+            .map(|path| {
+                let (text, tree) = &files[path];
+                (text.as_slice(), tree)
+            })
+            .collect();
+        let branches = Branches::new(&trees);
+        let kinds = branches.0.keys().copied().collect();
+        Splicer {
+            language: config.language,
+            branches,
+            chaos: config.chaos,
+            deletions: config.deletions,
+            kinds,
+            inter_splices: config.inter_splices,
+            max_size: config.max_size,
+            node_types: config.node_types,
+            trees,
+            reparse: config.reparse,
+            splice_rng: StdRng::seed_from_u64(seeds.splice),
+            deletion_rng: StdRng::seed_from_u64(seeds.deletion),
+            chaos_rng: StdRng::seed_from_u64(seeds.chaos),
+        }
+    }
+
+    fn delete_node(&mut self, tree: &Tree) -> (usize, Vec<u8>, isize) {
+        let chaotic = self.chaos_rng.gen_range(0..100) < self.chaos;
+        let optional: Vec<_> = all_nodes(tree.root_node())
+            .into_iter()
+            .skip(1)
+            .filter(|n| self.node_types.optional_node(n))
+            .collect();
+        let node = if chaotic {
+            pick_node(&mut self.chaos_rng, tree)
+        } else if optional.is_empty() {
+            pick_node(&mut self.deletion_rng, tree)
+        } else {
+            optional[self.deletion_rng.gen_range(0..optional.len())]
+        };
+        (node.id(), Vec::new(), Self::delta(node, &[]))
+    }
+
+    fn splice_node(&mut self, text: &[u8], tree: &Tree) -> (usize, Vec<u8>, isize) {
+        let chaotic = self.chaos_rng.gen_range(0..100) < self.chaos;
+
+        let mut node = tree.root_node();
+        let mut candidates = Vec::new();
+        // When modified trees are re-parsed, their nodes may have novel kinds
+        // not in Branches (candidates.len() == 0). Also, avoid not mutating
+        // (candidates.len() == 1).
+        while candidates.len() <= 1 {
+            node = pick_node(&mut self.splice_rng, tree);
+            candidates = if chaotic {
+                let kind = self.kinds[self.chaos_rng.gen_range(0..self.kinds.len())];
+                self.branches.0[kind].clone()
+            } else {
+                self.branches
+                    .0
+                    .get(node.kind())
+                    .cloned()
+                    .unwrap_or_default()
+            };
+        }
+
+        let mut candidate = candidates[self.splice_rng.gen_range(0..candidates.len())];
+        // Try to avoid not mutating
+        let node_text = &text[node.byte_range()];
+        while candidate == node_text {
+            candidate = candidates[self.splice_rng.gen_range(0..candidates.len())];
+        }
+        let replace = Vec::from(candidate);
+        let delta = Self::delta(node, &replace);
+        (node.id(), replace, delta)
+    }
+
+    pub(crate) fn splice_tree(&mut self, text0: &[u8], mut tree: Tree) -> Option<Vec<u8>> {
+        let mut edits = Edits::default();
+        if self.inter_splices == 0 {
+            return None;
+        }
+        let splices = self.splice_rng.gen_range(1..self.inter_splices);
+        let mut text = Vec::from(text0);
+        let mut sz = isize::try_from(text.len()).unwrap_or_default();
+        for i in 0..splices {
+            let (id, bytes, delta) = if self.deletion_rng.gen_range(0..100) < self.deletions {
+                self.delete_node(&tree)
+            } else {
+                self.splice_node(&text, &tree)
+            };
+            sz += delta;
+            let sized_out = usize::try_from(sz).unwrap_or_default() >= self.max_size;
+            edits.0.insert(id, bytes);
+            if i % self.reparse == 0 || i + 1 == splices || sized_out {
+                let mut result = Vec::with_capacity(usize::try_from(sz).unwrap_or_default());
+                tree_sitter_edit::render(&mut result, &tree, &text, &edits).ok()?;
+                text = result;
+                tree = parse(self.language, &text);
+                edits = Edits::default();
+            }
+            if sized_out {
+                break;
+            }
+        }
+        Some(text)
+    }
+}
+
+impl Iterator for Splicer<'_> {
+    type Item = Vec<u8>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let small: Vec<_> = self
+            .trees
+            .iter()
+            .filter(|(text, _)| text.len() <= self.max_size)
+            .copied()
+            .collect();
+        if small.is_empty() {
+            return None;
+        }
+        let (text, tree) = small[self.splice_rng.gen_range(0..small.len())];
+        self.splice_tree(text, tree.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The same files each time, but stored in a different order
+    fn corpus() -> HashMap<String, (Vec<u8>, Tree)> {
+        (0..16)
+            .map(|i| {
+                let text = format!("int f{i}(int x) {{ return x * {i}; }}").into_bytes();
+                let tree = parse(tree_sitter_c::language(), &text);
+                (format!("{i}.c"), (text, tree))
+            })
+            .collect()
+    }
+
+    fn config(chaos: u8, deletions: u8) -> Config {
+        Config {
+            chaos,
+            deletions,
+            language: tree_sitter_c::language(),
+            inter_splices: 16,
+            max_size: 1024,
+            node_types: NodeTypes::new(tree_sitter_c::NODE_TYPES).unwrap(),
+            reparse: 4,
+            seed: 42,
+        }
+    }
+
+    #[test]
+    fn same_seed_same_mutants() {
+        let mutants = |files: &HashMap<_, _>| {
+            Splicer::new(config(10, 10), files)
+                .take(32)
+                .collect::<Vec<_>>()
+        };
+        let first = mutants(&corpus());
+        assert_eq!(first.len(), 32);
+        assert_eq!(first, mutants(&corpus()));
+    }
+
+    #[test]
+    fn sub_seeds_replay_one_kind_of_edit() {
+        let files = corpus();
+        let seeds = SubSeeds::new(42);
+        let mutants = |chaos, deletions, seeds| {
+            Splicer::with_sub_seeds(config(chaos, deletions), seeds, &files)
+                .take(8)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            mutants(10, 10, seeds),
+            Splicer::new(config(10, 10), &files)
+                .take(8)
+                .collect::<Vec<_>>()
+        );
+        // Only splices, so the other seeds don't matter
+        let other = SubSeeds {
+            deletion: seeds.deletion + 1,
+            chaos: seeds.chaos + 1,
+            ..seeds
+        };
+        assert_eq!(mutants(0, 0, seeds), mutants(0, 0, other));
+        // Every edit a chaotic deletion, so the deletion seed doesn't matter
+        let other = SubSeeds {
+            deletion: seeds.deletion + 1,
+            ..seeds
+        };
+        let chaotic = mutants(100, 100, seeds);
+        assert_eq!(chaotic, mutants(100, 100, other));
+        let other = SubSeeds {
+            chaos: seeds.chaos + 1,
+            ..seeds
+        };
+        assert_ne!(chaotic, mutants(100, 100, other));
+    }
+}
@@ -0,0 +1,82 @@
+//! Helpers for the unit tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::Parser;
+use regex::Regex;
+use tempfile::TempDir;
+
+use crate::check::CmdCheck;
+use crate::{Args, RunStats};
+
+/// Never printed by the targets in the tests, so that a check captures their
+/// output without finding it interesting
+const NEVER: &str = "never printed by a test target";
+
+/// Parse a command line, without the program name
+pub(crate) fn args(argv: &[&str]) -> Args {
+    Args::try_parse_from(std::iter::once("tree-crasher").chain(argv.iter().copied())).unwrap()
+}
+
+/// Run on a corpus of C files
+pub(crate) fn run(args: &Args) -> anyhow::Result<RunStats> {
+    let stop = Arc::new(AtomicBool::new(false));
+    crate::run(
+        tree_sitter_c::language(),
+        tree_sitter_c::NODE_TYPES,
+        args.clone(),
+        stop,
+    )
+}
+
+/// A directory holding files with these names and contents
+pub(crate) fn corpus(files: &[(&str, &str)]) -> TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (name, contents) in files {
+        fs::write(dir.path().join(name), contents).unwrap();
+    }
+    dir
+}
+
+/// A check that runs `sh -c SCRIPT sh ARGS...`, captures its output, and finds
+/// exit code 1 interesting
+pub(crate) fn sh(script: &str, args: &[&str]) -> CmdCheck {
+    let never = || Some(Regex::new(NEVER).unwrap());
+    CmdCheck::new(
+        String::from("sh"),
+        ["-c", script, "sh"]
+            .iter()
+            .chain(args)
+            .map(|s| s.to_string())
+            .collect(),
+        vec![1],
+        None,
+        never(),
+        never(),
+        None,
+        None,
+        false,
+        false,
+        Some(Duration::from_secs(10)),
+    )
+}
+
+/// Compile a C program into `dir`, for targets that speak one of the protocols
+pub(crate) fn compile(dir: &Path, name: &str, src: &str) -> PathBuf {
+    let c = dir.join(format!("{name}.c"));
+    fs::write(&c, src).unwrap();
+    let exe = dir.join(name);
+    let status = Command::new("cc")
+        .arg("-o")
+        .arg(&exe)
+        .arg(&c)
+        .status()
+        .unwrap();
+    assert!(status.success(), "Failed to compile {}", c.display());
+    exe
+}